@@ -0,0 +1,261 @@
+use crate::error::PotError;
+use crate::ipam::IpDb;
+use crate::iprange::{next_addr, prev_addr, IpRange};
+use crate::lease::get_potnet_dir;
+use crate::{PotSystemConfig, Result};
+use ipnet::IpNet;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// How a free address is chosen among the available ones
+pub trait AllocationStrategy {
+    /// A free address of `range` for `pot`, None if the range is full
+    fn pick(&self, ip_db: &IpDb, range: &IpRange, pot: Option<&str>) -> Option<IpAddr>;
+}
+
+/// The lowest free address, the historical behavior
+#[derive(Debug, Default)]
+pub struct LowestFirst;
+
+impl AllocationStrategy for LowestFirst {
+    fn pick(&self, ip_db: &IpDb, range: &IpRange, _pot: Option<&str>) -> Option<IpAddr> {
+        ip_db.first_free(range)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HighestFirst;
+
+impl AllocationStrategy for HighestFirst {
+    fn pick(&self, ip_db: &IpDb, range: &IpRange, _pot: Option<&str>) -> Option<IpAddr> {
+        ip_db.last_free(range)
+    }
+}
+
+/// The first free address after the last allocation in the range, wrapping around
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    /// The last allocations, of all the pools and bridges
+    last: Vec<IpAddr>,
+}
+
+impl RoundRobin {
+    pub fn new(last: Vec<IpAddr>) -> Self {
+        RoundRobin { last }
+    }
+}
+
+impl AllocationStrategy for RoundRobin {
+    fn pick(&self, ip_db: &IpDb, range: &IpRange, _pot: Option<&str>) -> Option<IpAddr> {
+        match self.last.iter().find(|ip| range.contains(ip)) {
+            Some(last) => first_free_from(ip_db, range, next_addr(*last)?),
+            None => ip_db.first_free(range),
+        }
+    }
+}
+
+/// A free address at a random position, so that addresses are not reused soon
+#[derive(Debug, Default)]
+pub struct Random;
+
+impl AllocationStrategy for Random {
+    fn pick(&self, ip_db: &IpDb, range: &IpRange, _pot: Option<&str>) -> Option<IpAddr> {
+        // RandomState is seeded randomly by every process
+        let state = RandomState::new();
+        let high = u128::from(state.build_hasher().finish());
+        let mut hasher = state.build_hasher();
+        hasher.write_u8(1);
+        let offset = (high << 64) | u128::from(hasher.finish());
+        first_free_from(ip_db, range, range.at_offset(offset))
+    }
+}
+
+/// A free address at a position derived from the pot name, the same at every run;
+/// the lowest free address if the pot name is not known
+#[derive(Debug, Default)]
+pub struct HashOfName;
+
+impl AllocationStrategy for HashOfName {
+    fn pick(&self, ip_db: &IpDb, range: &IpRange, pot: Option<&str>) -> Option<IpAddr> {
+        match pot {
+            Some(pot) => first_free_from(ip_db, range, range.at_offset(u128::from(fnv1a(pot)))),
+            None => ip_db.first_free(range),
+        }
+    }
+}
+
+// the first free address of `range` starting from `candidate`, wrapping around
+fn first_free_from(ip_db: &IpDb, range: &IpRange, candidate: IpAddr) -> Option<IpAddr> {
+    let after = IpRange::new(candidate, range.end).filter(|_| range.contains(&candidate));
+    if let Some(ip) = after.and_then(|after| ip_db.first_free(&after)) {
+        return Some(ip);
+    }
+    let before = prev_addr(candidate).and_then(|end| IpRange::new(range.start, end));
+    before.and_then(|before| ip_db.first_free(&before))
+}
+
+// FNV-1a, stable across releases, unlike the hasher of the standard library
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// The allocation strategies, selected via POT_IP_ALLOCATION or potnet next --strategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    #[default]
+    Lowest,
+    Highest,
+    RoundRobin,
+    Random,
+    Hash,
+}
+
+impl FromStr for Strategy {
+    type Err = PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "lowest" => Ok(Strategy::Lowest),
+            "highest" => Ok(Strategy::Highest),
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "random" => Ok(Strategy::Random),
+            "hash" => Ok(Strategy::Hash),
+            _ => Err(PotError::ConfValueError(
+                "allocation strategy".to_string(),
+                s.to_string(),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Strategy::Lowest => "lowest",
+            Strategy::Highest => "highest",
+            Strategy::RoundRobin => "round-robin",
+            Strategy::Random => "random",
+            Strategy::Hash => "hash",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Strategy {
+    /// The implementation of the strategy; round-robin reads the last allocations
+    pub fn build(self, conf: &PotSystemConfig) -> Result<Box<dyn AllocationStrategy>> {
+        Ok(match self {
+            Strategy::Lowest => Box::new(LowestFirst),
+            Strategy::Highest => Box::new(HighestFirst),
+            Strategy::RoundRobin => Box::new(RoundRobin::new(
+                get_last_allocations(conf)?
+                    .into_iter()
+                    .map(|(_, ip)| ip)
+                    .collect(),
+            )),
+            Strategy::Random => Box::new(Random),
+            Strategy::Hash => Box::new(HashOfName),
+        })
+    }
+}
+
+/// The file with the last allocation of each network, used by the round-robin strategy
+pub fn get_last_allocations_path(conf: &PotSystemConfig) -> PathBuf {
+    get_potnet_dir(conf).join("last-allocations")
+}
+
+/// The last allocation of each network; lines that can't be parsed are ignored
+pub fn get_last_allocations(conf: &PotSystemConfig) -> Result<Vec<(IpNet, IpAddr)>> {
+    let s = match std::fs::read_to_string(get_last_allocations_path(conf)) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(s.lines()
+        .filter_map(|line| {
+            let (network, ip) = line.trim().split_once(' ')?;
+            Some((network.parse().ok()?, ip.trim().parse().ok()?))
+        })
+        .collect())
+}
+
+/// Record `ip` as the last allocation in `network`
+pub fn save_last_allocation(conf: &PotSystemConfig, network: &IpNet, ip: IpAddr) -> Result<()> {
+    let mut allocations = get_last_allocations(conf)?;
+    allocations.retain(|(n, _)| n != network);
+    allocations.push((*network, ip));
+    let content: String = allocations
+        .iter()
+        .map(|(network, ip)| format!("{} {}\n", network, ip))
+        .collect();
+    std::fs::create_dir_all(get_potnet_dir(conf))?;
+    crate::util::write_atomic(&get_last_allocations_path(conf), content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipam::Owner;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn ip_db() -> IpDb {
+        let mut ip_db = IpDb::new();
+        for i in &["10.192.0.1", "10.192.0.2", "10.192.0.5", "10.192.0.254"] {
+            ip_db.insert(ip(i), Owner::SystemGateway);
+        }
+        ip_db
+    }
+
+    #[test]
+    fn allocation_strategy_001() {
+        let range = IpRange::hosts(&"10.192.0.0/24".parse().unwrap());
+        let ip_db = ip_db();
+        assert_eq!(
+            LowestFirst.pick(&ip_db, &range, None),
+            Some(ip("10.192.0.3"))
+        );
+        assert_eq!(
+            HighestFirst.pick(&ip_db, &range, None),
+            Some(ip("10.192.0.253"))
+        );
+        let uut = RoundRobin::new(vec![ip("10.192.0.4")]);
+        assert_eq!(uut.pick(&ip_db, &range, None), Some(ip("10.192.0.6")));
+        let uut = RoundRobin::new(vec![ip("10.192.0.253")]);
+        assert_eq!(uut.pick(&ip_db, &range, None), Some(ip("10.192.0.3")));
+    }
+
+    #[test]
+    fn allocation_strategy_002() {
+        let range = IpRange::hosts(&"10.192.0.0/24".parse().unwrap());
+        let ip_db = ip_db();
+        let uut = HashOfName.pick(&ip_db, &range, Some("web"));
+        assert!(uut.is_some());
+        assert_eq!(HashOfName.pick(&ip_db, &range, Some("web")), uut);
+        assert_eq!(
+            HashOfName.pick(&ip_db, &range, None),
+            Some(ip("10.192.0.3"))
+        );
+        let uut = Random.pick(&ip_db, &range, None).unwrap();
+        assert!(range.contains(&uut) && !ip_db.contains(&uut));
+        let full = IpRange::new(ip("10.192.0.1"), ip("10.192.0.2")).unwrap();
+        assert_eq!(Random.pick(&ip_db, &full, None), None);
+        assert_eq!(HashOfName.pick(&ip_db, &full, Some("web")), None);
+    }
+
+    #[test]
+    fn strategy_fromstr_001() {
+        assert_eq!(
+            "round-robin".parse::<Strategy>().ok(),
+            Some(Strategy::RoundRobin)
+        );
+        assert_eq!(Strategy::RoundRobin.to_string(), "round-robin");
+        assert!("first".parse::<Strategy>().is_err());
+    }
+}
@@ -1,24 +1,491 @@
+use crate::confdoc::ConfDocument;
+use crate::diagnostic::{Diagnostic, Severity};
 use crate::error::PotError;
-use crate::Result;
+use crate::ipam::IpDb;
+use crate::iprange::{parse_range_list, IpRange};
+use crate::reservation::get_reservations;
+use crate::{get_pot_conf_list, get_pot_conf_reports, NetType, PotConf, Result};
 use ipnet::IpNet;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::net::IpAddr;
 use std::str::FromStr;
 
 use crate::PotSystemConfig;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+/// The valid bridges; see get_bridge_conf_reports for the problems of the others
 pub fn get_bridges_list(conf: &PotSystemConfig) -> Result<Vec<BridgeConf>> {
-    let path_list = get_bridges_path_list(conf);
-    let mut result = Vec::new();
-    for f in path_list {
-        if let Ok(conf_str) = std::fs::read_to_string(f.as_path()) {
-            if let Ok(bridge_conf) = conf_str.parse() {
-                result.push(bridge_conf);
+    Ok(get_bridge_conf_reports(conf)
+        .into_iter()
+        .filter_map(|r| r.conf)
+        .collect())
+}
+
+/// The configuration of a bridge, with the problems found in its file
+#[derive(Debug)]
+pub struct BridgeConfReport {
+    pub path: PathBuf,
+    /// None if the configuration cannot be understood
+    pub conf: Option<BridgeConf>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl BridgeConfReport {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error)
+    }
+}
+
+/// Read all the bridge configurations, reporting the problems found for each file
+pub fn get_bridge_conf_reports(conf: &PotSystemConfig) -> Vec<BridgeConfReport> {
+    get_bridges_path_list(conf)
+        .into_iter()
+        .map(|path| match std::fs::read_to_string(&path) {
+            Ok(conf_str) => analyze_bridge_conf(&path, &conf_str),
+            Err(e) => BridgeConfReport {
+                diagnostics: vec![Diagnostic::error(
+                    &path,
+                    &format!("cannot read the file: {}", e),
+                )],
+                path,
+                conf: None,
+            },
+        })
+        .collect()
+}
+
+// the parsing of BridgeConf::from_str, explaining why a file is not valid
+fn analyze_bridge_conf(path: &Path, conf_str: &str) -> BridgeConfReport {
+    let mut diagnostics = Vec::new();
+    let mut lines: HashMap<String, usize> = HashMap::new();
+    let partial = PartialBridgeConf::parse(conf_str, |line, key, valid| {
+        if lines.insert(key.to_string(), line).is_some() {
+            diagnostics.push(
+                Diagnostic::warning(path, "defined multiple times, the last one is used")
+                    .at(Some(line), key),
+            );
+        }
+        if !valid {
+            let reason = match key {
+                "reserved" => "invalid value, the whole bridge network is reserved",
+                _ => "invalid value",
+            };
+            diagnostics.push(Diagnostic::error(path, reason).at(Some(line), key));
+        }
+    });
+    for (key, present) in [
+        ("name", partial.name.is_some()),
+        ("net", partial.network.is_some()),
+        ("gateway", partial.gateway.is_some()),
+    ] {
+        if !present && !lines.contains_key(key) {
+            diagnostics.push(Diagnostic::error(path, &format!("{} is missing", key)));
+        }
+    }
+    if let (Some(network), Some(gateway)) = (partial.network, partial.gateway) {
+        if !network.contains(&gateway) {
+            diagnostics.push(
+                Diagnostic::error(path, "outside the bridge network")
+                    .at(lines.get("gateway").copied(), "gateway"),
+            );
+        }
+        for range in &partial.reserved {
+            if !network.contains(&range.start) || !network.contains(&range.end) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        path,
+                        &format!(
+                            "range {} outside the bridge network, the whole bridge network is reserved",
+                            range
+                        ),
+                    )
+                    .at(lines.get("reserved").copied(), "reserved"),
+                );
             }
         }
     }
-    Ok(result)
+    match (partial.network6, partial.gateway6) {
+        (Some(network6), Some(gateway6)) if !network6.contains(&gateway6) => diagnostics.push(
+            Diagnostic::error(path, "outside the bridge IPv6 network")
+                .at(lines.get("gateway6").copied(), "gateway6"),
+        ),
+        (None, Some(_)) if !lines.contains_key("net6") => diagnostics.push(
+            Diagnostic::error(path, "IPv6 gateway without net6")
+                .at(lines.get("gateway6").copied(), "gateway6"),
+        ),
+        _ => (),
+    }
+    let conf = BridgeConf::try_from(partial).ok().map(|bridge| BridgeConf {
+        path: Some(path.to_path_buf()),
+        ..bridge
+    });
+    BridgeConfReport {
+        path: path.to_path_buf(),
+        conf,
+        diagnostics,
+    }
+}
+
+/// The configuration of the bridge `name`
+pub fn get_bridge(conf: &PotSystemConfig, name: &str) -> Result<BridgeConf> {
+    get_bridges_list(conf)?
+        .into_iter()
+        .find(|x| x.name == name)
+        .ok_or_else(|| PotError::BridgeNotFound(name.to_string()))
+}
+
+// a name that can't escape the bridges directory nor break the file format
+fn is_valid_bridge_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && !name.contains("..")
+        && !name.contains(|c: char| c == '/' || c == '=' || c.is_whitespace())
+}
+
+/// The file of the bridge `name`, `fs_root`/bridges/`name`
+pub fn get_bridge_path(conf: &PotSystemConfig, name: &str) -> PathBuf {
+    Path::new(&conf.fs_root).join("bridges").join(name)
+}
+
+// the file `bridge` has been read from, that can be named differently from the bridge
+fn bridge_file(conf: &PotSystemConfig, bridge: &BridgeConf) -> PathBuf {
+    bridge
+        .path
+        .clone()
+        .unwrap_or_else(|| get_bridge_path(conf, &bridge.name))
+}
+
+/// The pots attached to `bridge`: the private-bridge pots naming it in their bridge key
+///
+/// The pots without a bridge key are attached if they have an address in its network
+pub fn get_bridge_members(conf: &PotSystemConfig, bridge: &BridgeConf) -> Vec<PotConf> {
+    get_pot_conf_list(conf.clone())
+        .into_iter()
+        .filter(|p| is_bridge_member(p, bridge))
+        .collect()
+}
+
+fn is_bridge_member(pot: &PotConf, bridge: &BridgeConf) -> bool {
+    pot.network_type == NetType::PrivateBridge
+        && match &pot.bridge {
+            Some(name) => *name == bridge.name,
+            None => pot.ip_addrs.iter().any(|ip| bridge.network.contains(ip)),
+        }
+}
+
+// the members of `bridge`, an error if a pot can't be understood: it could be one of them
+fn get_all_bridge_members(conf: &PotSystemConfig, bridge: &BridgeConf) -> Result<Vec<PotConf>> {
+    let reports = get_pot_conf_reports(conf);
+    let unknown: Vec<&str> = reports
+        .iter()
+        .filter(|r| r.conf.is_none())
+        .map(|r| r.name.as_str())
+        .collect();
+    if !unknown.is_empty() {
+        return Err(PotError::BridgeMembersUnknown(
+            bridge.name.clone(),
+            unknown.join(", "),
+        ));
+    }
+    Ok(reports
+        .into_iter()
+        .filter_map(|r| r.conf)
+        .filter(|p| is_bridge_member(p, bridge))
+        .collect())
+}
+
+// the first bridge, other than `bridge`, overlapping one of its networks
+fn find_overlapping<'a>(bridges: &'a [BridgeConf], bridge: &BridgeConf) -> Option<&'a BridgeConf> {
+    bridges
+        .iter()
+        .find(|b| b.name != bridge.name && b.overlaps(bridge))
+}
+
+/// Write the file of a new bridge
+///
+/// The network cannot overlap other bridges or addresses already taken in the pools
+pub fn create_bridge(conf: &PotSystemConfig, bridge: &BridgeConf) -> Result<()> {
+    create_bridges(conf, std::slice::from_ref(bridge))
+}
+
+/// Write the files of several new bridges, none of them if any of them cannot be created
+///
+/// The networks cannot overlap each other, other bridges or addresses already taken
+pub fn create_bridges(conf: &PotSystemConfig, bridges: &[BridgeConf]) -> Result<()> {
+    let existing = get_bridges_list(conf)?;
+    let ip_db = IpDb::load(conf)?;
+    for bridge in bridges {
+        if !is_valid_bridge_name(&bridge.name) {
+            return Err(PotError::InvalidBridgeName(bridge.name.clone()));
+        }
+        if get_bridge_path(conf, &bridge.name).exists() {
+            return Err(PotError::BridgeExists(bridge.name.clone()));
+        }
+        if let Some(other) = find_overlapping(&existing, bridge) {
+            return Err(PotError::BridgeOverlap(
+                bridge.name.clone(),
+                other.name.clone(),
+            ));
+        }
+        if let Some(network) = bridge.networks().find(|n| !ip_db.is_subnet_usable(n)) {
+            return Err(PotError::NetworkInUse(*network));
+        }
+    }
+    if let Some(finding) = validate_bridges(conf, bridges)
+        .into_iter()
+        .find(|f| f.problem.severity() == Severity::Error)
+    {
+        return Err(PotError::InvalidBridge(finding.to_string()));
+    }
+    let mut written = Vec::new();
+    for bridge in bridges {
+        let path = get_bridge_path(conf, &bridge.name);
+        let result = std::fs::create_dir_all(path.parent().unwrap())
+            .map_err(PotError::from)
+            .and_then(|_| bridge.to_document().write(&path));
+        if let Err(e) = result {
+            // the files already written are removed, as if nothing happened
+            for path in &written {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(e);
+        }
+        written.push(path);
+    }
+    Ok(())
+}
+
+/// Remove the file of the bridge `name`, if no pot uses it anymore
+///
+/// All the pot configurations have to be understood, to know that no pot uses it
+pub fn delete_bridge(conf: &PotSystemConfig, name: &str) -> Result<BridgeConf> {
+    let bridge = get_bridge(conf, name)?;
+    let members = get_all_bridge_members(conf, &bridge)?;
+    if !members.is_empty() {
+        let names: Vec<&str> = members.iter().map(|p| p.name.as_str()).collect();
+        return Err(PotError::BridgeInUse(name.to_string(), names.join(", ")));
+    }
+    std::fs::remove_file(bridge_file(conf, &bridge))?;
+    Ok(bridge)
+}
+
+/// Move the bridge `name` to `network`, keeping the rest of its file
+///
+/// The gateway, the reserved ranges, the reservations and the addresses of the members
+/// have to fit in the new network; the addresses added cannot be already taken,
+/// and the bridge has to stay valid, see validate_bridges
+pub fn resize_bridge(conf: &PotSystemConfig, name: &str, network: IpNet) -> Result<BridgeConf> {
+    let mut bridge = get_bridge(conf, name)?;
+    let path = bridge_file(conf, &bridge);
+    let outside = |what: String| PotError::BridgeMemberOutside(name.to_string(), what);
+    if !network.contains(&bridge.gateway) {
+        return Err(outside(format!("gateway {}", bridge.gateway)));
+    }
+    if let Some(r) = bridge
+        .reserved
+        .iter()
+        .find(|r| !network.contains(&r.start) || !network.contains(&r.end))
+    {
+        return Err(outside(format!("reserved range {}", r)));
+    }
+    for pot in get_all_bridge_members(conf, &bridge)? {
+        if let Some(ip) = pot.ip_addrs.iter().find(|ip| !network.contains(*ip)) {
+            return Err(outside(format!("pot {} ({})", pot.name, ip)));
+        }
+    }
+    if let Some(r) = get_reservations(conf)?.iter().find(|r| {
+        r.bridge.as_deref() == Some(name)
+            && bridge.network.contains(&r.ip)
+            && !network.contains(&r.ip)
+    }) {
+        return Err(outside(format!("reservation of {} ({})", r.pot, r.ip)));
+    }
+    let old = IpRange::from_net(&bridge.network);
+    bridge.network = network;
+    let mut bridges = get_bridges_list(conf)?;
+    if let Some(other) = find_overlapping(&bridges, &bridge) {
+        return Err(PotError::BridgeOverlap(
+            name.to_string(),
+            other.name.clone(),
+        ));
+    }
+    // e.g. the gateway can become the network or the broadcast address
+    for b in bridges.iter_mut().filter(|b| b.path == bridge.path) {
+        *b = bridge.clone();
+    }
+    if let Some(finding) = validate_bridges(conf, &bridges)
+        .into_iter()
+        .find(|f| f.bridge == name && f.problem.severity() == Severity::Error)
+    {
+        return Err(PotError::InvalidBridge(finding.to_string()));
+    }
+    // the addresses taken in the new network have to belong to the old one
+    let new = IpRange::from_net(&network);
+    if IpDb::load(conf)?.iter().any(|(range, _)| {
+        range.overlaps(&new)
+            && !(old.contains(&range.start.max(new.start)) && old.contains(&range.end.min(new.end)))
+    }) {
+        return Err(PotError::NetworkInUse(network));
+    }
+    let mut document = ConfDocument::read(&path)?;
+    document.set("net", &network.to_string());
+    document.write(&path)?;
+    Ok(bridge)
+}
+
+/// A problem of a bridge, found by validate_bridges
+#[derive(Debug, Clone, PartialEq)]
+pub enum BridgeProblem {
+    /// The gateway is the network or the broadcast address, or the Subnet-Router
+    /// anycast address for IPv6
+    InvalidGateway(IpAddr),
+    /// The name is not the one of the bridge file
+    NameMismatch(String),
+    /// The name can't be used as the name of a network interface
+    InvalidName,
+    /// Another bridge has the same name
+    DuplicateName,
+    /// The network overlaps the one of another bridge
+    Overlap(String),
+    /// The network contains an address of the system configuration, e.g. the gateway
+    SystemAddress(&'static str, IpAddr),
+    /// The network overlaps a POT_NETWORK_RESERVED range
+    ReservedRange(IpRange),
+}
+
+impl BridgeProblem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            BridgeProblem::NameMismatch(_) | BridgeProblem::ReservedRange(_) => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl std::fmt::Display for BridgeProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BridgeProblem::InvalidGateway(ip) => {
+                write!(f, "gateway {} is not a host address of the network", ip)
+            }
+            BridgeProblem::NameMismatch(file_name) => {
+                write!(f, "name different from the file name {}", file_name)
+            }
+            BridgeProblem::InvalidName => write!(f, "name not valid as an interface name"),
+            BridgeProblem::DuplicateName => write!(f, "defined multiple times"),
+            BridgeProblem::Overlap(other) => write!(f, "overlaps the bridge {}", other),
+            BridgeProblem::SystemAddress(what, ip) => write!(f, "contains the {} {}", what, ip),
+            BridgeProblem::ReservedRange(range) => {
+                write!(f, "overlaps the POT_NETWORK_RESERVED range {}", range)
+            }
+        }
+    }
+}
+
+/// A problem of the bridge `bridge`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgeFinding {
+    pub bridge: String,
+    pub network: IpNet,
+    pub problem: BridgeProblem,
+}
+
+impl std::fmt::Display for BridgeFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bridge {} ({}): {}",
+            self.bridge, self.network, self.problem
+        )
+    }
+}
+
+// FreeBSD interface names are shorter than IFNAMSIZ (16)
+fn is_valid_interface_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() < 16
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Check the bridges one by one, against each other and against the system configuration
+pub fn validate_bridges(conf: &PotSystemConfig, bridges: &[BridgeConf]) -> Vec<BridgeFinding> {
+    let mut findings = Vec::new();
+    for (i, bridge) in bridges.iter().enumerate() {
+        let mut push = |problem| {
+            findings.push(BridgeFinding {
+                bridge: bridge.name.clone(),
+                network: bridge.network,
+                problem,
+            })
+        };
+        let network = &bridge.network;
+        if bridge.gateway == network.network()
+            || (bridge.gateway.is_ipv4() && bridge.gateway == network.broadcast())
+        {
+            push(BridgeProblem::InvalidGateway(bridge.gateway));
+        }
+        // the network address of an IPv6 network is the Subnet-Router anycast address
+        if let (Some(network6), Some(gateway6)) = (bridge.network6, bridge.gateway6) {
+            if gateway6 == network6.network() {
+                push(BridgeProblem::InvalidGateway(gateway6));
+            }
+        }
+        if let Some(file_name) = bridge
+            .path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_string())
+        {
+            if file_name != bridge.name {
+                push(BridgeProblem::NameMismatch(file_name));
+            }
+        }
+        if !is_valid_interface_name(&bridge.name) {
+            push(BridgeProblem::InvalidName);
+        }
+        for other in &bridges[i + 1..] {
+            if other.name == bridge.name {
+                push(BridgeProblem::DuplicateName);
+            } else if bridge.overlaps(other) {
+                push(BridgeProblem::Overlap(other.name.clone()));
+            }
+        }
+        for pool in conf.pools() {
+            let system = [
+                ("network address", Some(pool.network.network())),
+                ("broadcast address", Some(pool.network.broadcast())),
+                ("gateway IP", Some(pool.gateway)),
+                ("DNS IP", pool.dns_ip),
+            ];
+            for (what, ip) in system.iter().filter_map(|(what, ip)| Some((*what, (*ip)?))) {
+                if bridge.networks().any(|n| n.contains(&ip)) {
+                    push(BridgeProblem::SystemAddress(what, ip));
+                }
+            }
+        }
+        for range in conf
+            .reserved
+            .iter()
+            .filter(|r| bridge.networks().any(|n| r.overlaps(&IpRange::from_net(n))))
+        {
+            push(BridgeProblem::ReservedRange(*range));
+        }
+    }
+    findings
+}
+
+/// All the bridge checks: the problems of each bridge file and the findings of
+/// validate_bridges for the bridges that can be understood
+pub fn check_bridges(conf: &PotSystemConfig) -> (Vec<BridgeConfReport>, Vec<BridgeFinding>) {
+    let reports = get_bridge_conf_reports(conf);
+    let bridges: Vec<BridgeConf> = reports.iter().filter_map(|r| r.conf.clone()).collect();
+    let findings = validate_bridges(conf, &bridges);
+    (reports, findings)
 }
 
 fn get_bridges_path_list(conf: &PotSystemConfig) -> Vec<PathBuf> {
@@ -33,11 +500,105 @@ fn get_bridges_path_list(conf: &PotSystemConfig) -> Vec<PathBuf> {
         .for_each(|x| result.push(x.into_path()));
     result
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BridgeConf {
     pub name: String,
     pub network: IpNet,
     pub gateway: IpAddr,
+    /// The addresses of the bridge never handed out by potnet
+    pub reserved: Vec<IpRange>,
+    /// The IPv6 network of the bridge, in addition to the IPv4 one
+    pub network6: Option<IpNet>,
+    pub gateway6: Option<IpAddr>,
+    pub mtu: Option<u16>,
+    /// The VLAN tag, to connect the bridge to a trunk
+    pub vlan: Option<u16>,
+    pub description: Option<String>,
+    /// If true, the pots of the bridge cannot talk to each other
+    pub isolated: bool,
+    /// The file the configuration has been read from, if any
+    pub path: Option<PathBuf>,
+}
+
+impl BridgeConf {
+    /// A bridge with the gateway at the first host address of `network`
+    ///
+    /// The name has to be usable as the name of the bridge file
+    pub fn new(name: &str, network: IpNet) -> Result<Self> {
+        if !is_valid_bridge_name(name) {
+            return Err(PotError::InvalidBridgeName(name.to_string()));
+        }
+        let gateway = BridgeConf::first_gateway(&network)?;
+        Ok(BridgeConf {
+            name: name.to_string(),
+            network,
+            gateway,
+            reserved: Vec::new(),
+            network6: None,
+            gateway6: None,
+            mtu: None,
+            vlan: None,
+            description: None,
+            isolated: false,
+            path: None,
+        })
+    }
+
+    /// The first host address of `network`, Subnet-Router anycast address excluded
+    ///
+    /// A network without addresses left for the pots after the gateway is too small
+    pub fn first_gateway(network: &IpNet) -> Result<IpAddr> {
+        let hosts = IpRange::hosts(network);
+        if network.max_prefix_len() - network.prefix_len() < 2 {
+            return Err(PotError::NetworkTooSmall(hosts.span() + 1));
+        }
+        Ok(hosts.start)
+    }
+
+    /// The networks of the bridge, the IPv4 one first
+    pub fn networks(&self) -> impl Iterator<Item = &IpNet> {
+        std::iter::once(&self.network).chain(&self.network6)
+    }
+
+    /// If one of the networks of the bridge overlaps one of the networks of `other`
+    pub fn overlaps(&self, other: &BridgeConf) -> bool {
+        self.networks().any(|n| {
+            other
+                .networks()
+                .any(|o| n.contains(&o.network()) || o.contains(&n.network()))
+        })
+    }
+
+    /// The content of the bridge file
+    pub fn to_document(&self) -> ConfDocument {
+        let mut document = ConfDocument::new();
+        document.set("name", &self.name);
+        document.set("net", &self.network.to_string());
+        document.set("gateway", &self.gateway.to_string());
+        if !self.reserved.is_empty() {
+            let reserved: Vec<String> = self.reserved.iter().map(|r| r.to_string()).collect();
+            document.set("reserved", &reserved.join(","));
+        }
+        if let Some(network6) = self.network6 {
+            document.set("net6", &network6.to_string());
+        }
+        if let Some(gateway6) = self.gateway6 {
+            document.set("gateway6", &gateway6.to_string());
+        }
+        if let Some(mtu) = self.mtu {
+            document.set("mtu", &mtu.to_string());
+        }
+        if let Some(vlan) = self.vlan {
+            document.set("vlan", &vlan.to_string());
+        }
+        if let Some(description) = &self.description {
+            document.set("description", description);
+        }
+        if self.isolated {
+            document.set("isolated", "true");
+        }
+        document
+    }
 }
 
 impl FromStr for BridgeConf {
@@ -57,13 +618,37 @@ impl TryFrom<PartialBridgeConf> for BridgeConf {
         } else {
             let network = value.network.unwrap();
             let gateway = value.gateway.unwrap();
-            if !network.contains(&gateway) {
+            // ignoring a broken reserved= would hand out the addresses it reserves
+            let reserved = if value.reserved_invalid
+                || value
+                    .reserved
+                    .iter()
+                    .any(|r| !network.contains(&r.start) || !network.contains(&r.end))
+            {
+                vec![IpRange::hosts(&network)]
+            } else {
+                value.reserved
+            };
+            let gateway6_valid = match (value.network6, value.gateway6) {
+                (Some(network6), Some(gateway6)) => network6.contains(&gateway6),
+                (None, Some(_)) => false,
+                _ => true,
+            };
+            if !network.contains(&gateway) || !gateway6_valid {
                 Err(PotError::BridgeConfError)
             } else {
                 Ok(BridgeConf {
                     name: value.name.unwrap(),
-                    network: value.network.unwrap(),
-                    gateway: value.gateway.unwrap(),
+                    network,
+                    gateway,
+                    reserved,
+                    network6: value.network6,
+                    gateway6: value.gateway6,
+                    mtu: value.mtu,
+                    vlan: value.vlan,
+                    description: value.description,
+                    isolated: value.isolated,
+                    path: None,
                 })
             }
         }
@@ -75,77 +660,492 @@ struct PartialBridgeConf {
     name: Option<String>,
     network: Option<IpNet>,
     gateway: Option<IpAddr>,
+    reserved: Vec<IpRange>,
+    network6: Option<IpNet>,
+    gateway6: Option<IpAddr>,
+    mtu: Option<u16>,
+    vlan: Option<u16>,
+    description: Option<String>,
+    isolated: bool,
+    /// The value of reserved is not valid: the whole network is reserved
+    reserved_invalid: bool,
+    /// The keys with a value not valid, making the whole configuration not valid
+    invalid: Vec<String>,
 }
 
 impl FromStr for PartialBridgeConf {
     type Err = std::convert::Infallible;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        use crate::util::get_value;
-        let lines: Vec<String> = s
-            .to_string()
-            .lines()
-            .map(|x| x.trim().to_string())
-            .filter(|x| !x.starts_with('#'))
-            .collect();
+        Ok(PartialBridgeConf::parse(s, |_, _, _| ()))
+    }
+}
+
+impl PartialBridgeConf {
+    // the configuration in `s`, calling `on_set` for every known key with its line number
+    // and if its value is valid
+    fn parse(s: &str, mut on_set: impl FnMut(usize, &str, bool)) -> Self {
+        use crate::util::parse_assignment;
         let mut result = PartialBridgeConf::default();
-        for linestr in &lines {
-            if linestr.starts_with("name=") {
-                result.name = get_value(linestr);
-            }
-            if linestr.starts_with("net=") {
-                result.network = get_value(linestr);
-            }
-            if linestr.starts_with("gateway=") {
-                result.gateway = get_value(linestr);
+        for (i, line) in s.lines().enumerate() {
+            if let Some((key, value)) = parse_assignment(line) {
+                if let Some(valid) = result.set(&key, &value) {
+                    on_set(i + 1, &key, valid);
+                }
             }
         }
-        Ok(result)
+        result
     }
-}
 
-impl PartialBridgeConf {
     fn is_valid(&self) -> bool {
-        self.name.is_some() && self.network.is_some() && self.gateway.is_some()
+        self.name.is_some()
+            && self.network.is_some()
+            && self.gateway.is_some()
+            && self.invalid.is_empty()
+    }
+
+    // None if the key is unknown, otherwise if the value is valid;
+    // as in the shell, the last assignment wins
+    fn set(&mut self, key: &str, value: &str) -> Option<bool> {
+        let valid = match key {
+            "name" => {
+                self.name = Some(value.to_string());
+                true
+            }
+            "net" => {
+                self.network = value.parse().ok();
+                self.network.is_some()
+            }
+            "gateway" => {
+                self.gateway = value.parse().ok();
+                self.gateway.is_some()
+            }
+            "reserved" => {
+                let reserved = parse_range_list(value);
+                self.reserved_invalid = reserved.is_err();
+                self.reserved = reserved.unwrap_or_default();
+                !self.reserved_invalid
+            }
+            "net6" => {
+                self.network6 = value.parse().ok().filter(|n| matches!(n, IpNet::V6(_)));
+                self.network6.is_some()
+            }
+            "gateway6" => {
+                self.gateway6 = value.parse().ok().filter(IpAddr::is_ipv6);
+                self.gateway6.is_some()
+            }
+            "mtu" => {
+                self.mtu = value.parse().ok().filter(|mtu| *mtu >= 576);
+                self.mtu.is_some()
+            }
+            "vlan" => {
+                self.vlan = value.parse().ok().filter(|vlan| (1..=4094).contains(vlan));
+                self.vlan.is_some()
+            }
+            "description" => {
+                self.description = Some(value.to_string());
+                true
+            }
+            "isolated" => match value {
+                "true" | "false" => {
+                    self.isolated = value == "true";
+                    true
+                }
+                _ => false,
+            },
+            _ => return None,
+        };
+        self.invalid.retain(|k| k != key);
+        if !valid && key != "reserved" {
+            self.invalid.push(key.to_string());
+        }
+        Some(valid)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tests::test_conf;
+
+    #[test]
+    fn bridge_lifecycle_001() {
+        let fs_root = tempfile::tempdir().unwrap();
+        let conf = test_conf(fs_root.path());
+        let uut = BridgeConf::new("backend", "10.192.1.0/28".parse().unwrap()).unwrap();
+        assert_eq!(uut.gateway, "10.192.1.1".parse::<IpAddr>().unwrap());
+        create_bridge(&conf, &uut).unwrap();
+        let path = get_bridge_path(&conf, "backend");
+        assert_eq!(
+            get_bridge(&conf, "backend").ok(),
+            Some(BridgeConf {
+                path: Some(path.clone()),
+                ..uut.clone()
+            })
+        );
+        assert!(matches!(
+            create_bridge(&conf, &uut),
+            Err(PotError::BridgeExists(_))
+        ));
+        let other = BridgeConf::new("other", "10.192.1.0/27".parse().unwrap()).unwrap();
+        assert!(matches!(
+            create_bridge(&conf, &other),
+            Err(PotError::BridgeOverlap(_, _))
+        ));
+        let other = BridgeConf::new("other", "10.192.0.0/28".parse().unwrap()).unwrap();
+        assert!(matches!(
+            create_bridge(&conf, &other),
+            Err(PotError::NetworkInUse(_))
+        ));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, format!("# the backend\n{}", content)).unwrap();
+        let pot_dir = fs_root.path().join("jails").join("db").join("conf");
+        std::fs::create_dir_all(&pot_dir).unwrap();
+        std::fs::write(
+            pot_dir.join("pot.conf"),
+            "network_type=private-bridge\nip=10.192.1.10\n",
+        )
+        .unwrap();
+        let uut = resize_bridge(&conf, "backend", "10.192.1.0/27".parse().unwrap()).unwrap();
+        assert_eq!(uut.network, "10.192.1.0/27".parse::<IpNet>().unwrap());
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("# the backend\nname=backend\nnet=10.192.1.0/27\n"));
+        assert!(matches!(
+            resize_bridge(&conf, "backend", "10.192.1.0/29".parse().unwrap()),
+            Err(PotError::BridgeMemberOutside(_, _))
+        ));
+        assert!(matches!(
+            delete_bridge(&conf, "backend"),
+            Err(PotError::BridgeInUse(_, _))
+        ));
+        // the bridge key decides, the address only for the pots without it
+        std::fs::write(
+            pot_dir.join("pot.conf"),
+            "network_type=private-bridge\nip=10.192.1.10\nbridge=other\n",
+        )
+        .unwrap();
+        assert!(get_bridge_members(&conf, &uut).is_empty());
+        std::fs::write(
+            pot_dir.join("pot.conf"),
+            "network_type=private-bridge\nip=10.192.2.10\nbridge=backend\n",
+        )
+        .unwrap();
+        assert_eq!(get_bridge_members(&conf, &uut).len(), 1);
+        assert!(matches!(
+            delete_bridge(&conf, "backend"),
+            Err(PotError::BridgeInUse(_, _))
+        ));
+        std::fs::write(pot_dir.join("pot.conf"), "network_type=private-bridge\n").unwrap();
+        assert!(matches!(
+            delete_bridge(&conf, "backend"),
+            Err(PotError::BridgeMembersUnknown(_, _))
+        ));
+        std::fs::remove_dir_all(fs_root.path().join("jails").join("db")).unwrap();
+        assert!(delete_bridge(&conf, "backend").is_ok());
+        assert!(!path.exists());
+        assert!(matches!(
+            delete_bridge(&conf, "backend"),
+            Err(PotError::BridgeNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn bridge_lifecycle_002() {
+        let fs_root = tempfile::tempdir().unwrap();
+        let conf = test_conf(fs_root.path());
+        let bridges_dir = fs_root.path().join("bridges");
+        std::fs::create_dir_all(&bridges_dir).unwrap();
+        // the bridge is found by its name, the file it is read from is the one changed
+        let path = bridges_dir.join("backend.conf");
+        std::fs::write(
+            &path,
+            "name=backend\nnet=10.192.1.0/28\ngateway=10.192.1.1\n",
+        )
+        .unwrap();
+        let uut = resize_bridge(&conf, "backend", "10.192.1.0/27".parse().unwrap()).unwrap();
+        assert_eq!(uut.path, Some(path.clone()));
+        // the resized bridge has to be valid: here the gateway would be the broadcast address
+        assert!(matches!(
+            resize_bridge(&conf, "backend", "10.192.1.0/31".parse().unwrap()),
+            Err(PotError::InvalidBridge(_))
+        ));
+        assert!(!get_bridge_path(&conf, "backend").exists());
+        assert_eq!(
+            get_bridge(&conf, "backend").unwrap().network,
+            "10.192.1.0/27".parse::<IpNet>().unwrap()
+        );
+        assert!(delete_bridge(&conf, "backend").is_ok());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn bridge_conf_new_001() {
+        let uut = BridgeConf::new("backend", "fd00:1::/64".parse().unwrap()).unwrap();
+        assert_eq!(uut.gateway, "fd00:1::1".parse::<IpAddr>().unwrap());
+        let uut = BridgeConf::new("backend", "10.192.1.0/30".parse().unwrap()).unwrap();
+        assert_eq!(uut.gateway, "10.192.1.1".parse::<IpAddr>().unwrap());
+        for net in &[
+            "10.192.1.0/31",
+            "10.192.1.0/32",
+            "fd00:1::/127",
+            "fd00:1::/128",
+        ] {
+            assert!(matches!(
+                BridgeConf::new("backend", net.parse().unwrap()),
+                Err(PotError::NetworkTooSmall(_))
+            ));
+        }
+        for name in &[
+            "",
+            ".",
+            "..",
+            "../../etc/x",
+            "a/b",
+            "back end",
+            "a\tb",
+            "a=b",
+        ] {
+            assert!(
+                matches!(
+                    BridgeConf::new(name, "10.192.1.0/28".parse().unwrap()),
+                    Err(PotError::InvalidBridgeName(n)) if n == *name
+                ),
+                "{:?}",
+                name
+            );
+        }
+        let fs_root = tempfile::tempdir().unwrap();
+        let conf = test_conf(fs_root.path());
+        let escaping = BridgeConf {
+            name: "../x".to_string(),
+            ..BridgeConf::new("x", "10.192.1.0/28".parse().unwrap()).unwrap()
+        };
+        assert!(matches!(
+            create_bridge(&conf, &escaping),
+            Err(PotError::InvalidBridgeName(_))
+        ));
+        assert!(!fs_root.path().join("x").exists());
+    }
+
+    #[test]
+    fn analyze_bridge_conf_001() {
+        let path = Path::new("/opt/pot/bridges/test-bridge");
+        let uut = analyze_bridge_conf(
+            path,
+            "net=10.192.0.24/29\ngateway=10.192.0.25\nname=test-bridge\n",
+        );
+        assert!(uut.diagnostics.is_empty());
+        assert!(uut.conf.is_some());
+        let uut = analyze_bridge_conf(path, "net=10.192.0.24/29\ngateway=10.192.0.33\n");
+        assert!(uut.conf.is_none());
+        assert!(uut.has_errors());
+        assert_eq!(uut.diagnostics.len(), 2);
+        assert_eq!(uut.diagnostics[0].reason, "name is missing".to_string());
+        assert_eq!(uut.diagnostics[1].line, Some(2));
+        let uut = analyze_bridge_conf(path, "net=10.192.0.24/33\ngateway=10.192.0.25\nname=other");
+        assert!(uut.conf.is_none());
+        assert_eq!(uut.diagnostics.len(), 1);
+        assert_eq!(uut.diagnostics[0].key, Some("net".to_string()));
+        let uut = analyze_bridge_conf(path, "net=10.192.0.24/29\ngateway=10.192.0.25\nname=other");
+        assert!(uut.conf.is_some());
+        assert!(!uut.has_errors());
+    }
+
+    #[test]
+    fn bridge_conf_fromstr_023() {
+        let conf = "net=10.192.0.24/29\ngateway=10.192.0.25\nname=test-bridge\n";
+        let uut = BridgeConf::from_str(conf).unwrap();
+        assert_eq!(uut.network6, None);
+        assert_eq!(uut.mtu, None);
+        assert!(!uut.isolated);
+        let extended = format!(
+            "{}net6=fd00:1:1::/64\ngateway6=fd00:1:1::1\nmtu=9000\nvlan=42\n\
+             description='the backend'\nisolated=true\n",
+            conf
+        );
+        let uut = BridgeConf::from_str(&extended).unwrap();
+        assert_eq!(uut.network6, Some("fd00:1:1::/64".parse().unwrap()));
+        assert_eq!(uut.gateway6, Some("fd00:1:1::1".parse().unwrap()));
+        assert_eq!(uut.mtu, Some(9000));
+        assert_eq!(uut.vlan, Some(42));
+        assert_eq!(uut.description, Some("the backend".to_string()));
+        assert!(uut.isolated);
+        assert_eq!(
+            BridgeConf::from_str(&uut.to_document().to_string()).ok(),
+            Some(uut)
+        );
+        for broken in &[
+            "vlan=4095",
+            "mtu=100",
+            "isolated=yes",
+            "net6=10.192.1.0/24",
+            "gateway6=fd00:1:1::1",
+            "net6=fd00:1:1::/64\ngateway6=fd00:1:2::1",
+        ] {
+            assert!(BridgeConf::from_str(&format!("{}{}", conf, broken)).is_err());
+        }
+        let uut = analyze_bridge_conf(
+            Path::new("test-bridge"),
+            &format!("{}vlan=0\ngateway6=fd00:1:1::1", conf),
+        );
+        assert_eq!(uut.diagnostics.len(), 2);
+        assert_eq!(uut.diagnostics[0].key, Some("vlan".to_string()));
+        assert_eq!(uut.diagnostics[1].key, Some("gateway6".to_string()));
+    }
+
+    #[test]
+    fn validate_bridges_001() {
+        let conf = crate::tests::test_conf(Path::new("/opt/pot"));
+        let bridge = |name: &str, net: &str, path: Option<&str>| BridgeConf {
+            path: path.map(PathBuf::from),
+            ..BridgeConf::new(name, net.parse().unwrap()).unwrap()
+        };
+        let bridges = [
+            bridge("backend", "10.192.1.0/28", Some("/opt/pot/bridges/backend")),
+            bridge("frontend", "10.192.2.0/28", Some("/opt/pot/bridges/front")),
+        ];
+        let uut = validate_bridges(&conf, &bridges);
+        assert_eq!(uut.len(), 1);
+        assert_eq!(
+            uut[0].problem,
+            BridgeProblem::NameMismatch("front".to_string())
+        );
+        assert_eq!(uut[0].problem.severity(), Severity::Warning);
+        let broken = BridgeConf {
+            name: "back end".to_string(),
+            gateway: "10.192.1.15".parse().unwrap(),
+            ..bridge("backend", "10.192.1.8/29", None)
+        };
+        let uut: Vec<BridgeProblem> = validate_bridges(&conf, &[broken, bridges[0].clone()])
+            .into_iter()
+            .map(|f| f.problem)
+            .collect();
+        assert_eq!(
+            uut,
+            vec![
+                BridgeProblem::InvalidGateway("10.192.1.15".parse().unwrap()),
+                BridgeProblem::InvalidName,
+                BridgeProblem::Overlap("backend".to_string()),
+            ]
+        );
+        let uut = validate_bridges(&conf, &[bridge("sys", "10.192.0.0/30", None)]);
+        assert_eq!(uut.len(), 3);
+        assert!(matches!(
+            uut[0].problem,
+            BridgeProblem::SystemAddress("network address", _)
+        ));
+        // the IPv6 networks are checked too
+        let conf = PotSystemConfig {
+            network6: Some("fd00::/48".parse().unwrap()),
+            gateway6: Some("fd00::1".parse().unwrap()),
+            ..conf
+        };
+        let dual = |name: &str, net: &str, net6: &str| {
+            let network6: IpNet = net6.parse().unwrap();
+            BridgeConf {
+                network6: Some(network6),
+                gateway6: Some(network6.network()),
+                ..bridge(name, net, None)
+            }
+        };
+        let uut: Vec<BridgeProblem> = validate_bridges(
+            &conf,
+            &[
+                dual("backend", "10.192.1.0/28", "fd00:0:0:1::/64"),
+                dual("frontend", "10.192.2.0/28", "fd00:0:0:1::/80"),
+                dual("sys", "10.192.3.0/28", "fd00::/64"),
+            ],
+        )
+        .into_iter()
+        .map(|f| f.problem)
+        .collect();
+        assert_eq!(
+            uut,
+            vec![
+                BridgeProblem::InvalidGateway("fd00:0:0:1::".parse().unwrap()),
+                BridgeProblem::Overlap("frontend".to_string()),
+                BridgeProblem::InvalidGateway("fd00:0:0:1::".parse().unwrap()),
+                BridgeProblem::InvalidGateway("fd00::".parse().unwrap()),
+                BridgeProblem::SystemAddress("network address", "fd00::".parse().unwrap()),
+                BridgeProblem::SystemAddress("gateway IP", "fd00::1".parse().unwrap()),
+            ]
+        );
+        assert!(find_overlapping(
+            &[dual("frontend", "10.192.2.0/28", "fd00:0:0:1::/80")],
+            &dual("backend", "10.192.1.0/28", "fd00:0:0:1::/64")
+        )
+        .is_some());
+    }
 
     #[test]
     fn bridge_conf_fromstr_001() {
         let uut = BridgeConf::from_str("");
-        assert_eq!(uut.is_ok(), false);
+        assert!(uut.is_err());
     }
 
     #[test]
     fn bridge_conf_fromstr_002() {
         let uut = BridgeConf::from_str("net=10.192.0.24/29");
-        assert_eq!(uut.is_ok(), false);
+        assert!(uut.is_err());
     }
 
     #[test]
     fn bridge_conf_fromstr_003() {
         let uut = BridgeConf::from_str("gateway=10.192.0.24");
-        assert_eq!(uut.is_ok(), false);
+        assert!(uut.is_err());
     }
 
     #[test]
     fn bridge_conf_fromstr_004() {
         let uut = BridgeConf::from_str("name=test-bridge");
-        assert_eq!(uut.is_ok(), false);
+        assert!(uut.is_err());
     }
 
     #[test]
     fn bridge_conf_fromstr_005() {
         let uut = BridgeConf::from_str("net=10.192.0.24/29\ngateway=10.192.1.25\nname=test-bridge");
-        assert_eq!(uut.is_ok(), false);
+        assert!(uut.is_err());
     }
 
     #[test]
     fn bridge_conf_fromstr_020() {
         let uut = BridgeConf::from_str("net=10.192.0.24/29\ngateway=10.192.0.25\nname=test-bridge");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
+    }
+
+    #[test]
+    fn bridge_conf_fromstr_021() {
+        let uut = BridgeConf::from_str(
+            "# private bridge\nnet=\"10.192.0.24/29\"\t# network\ngateway='10.192.0.25'\nname=test-bridge",
+        );
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.name, "test-bridge".to_string());
+        assert_eq!(uut.network, "10.192.0.24/29".parse::<IpNet>().unwrap());
+    }
+
+    #[test]
+    fn bridge_conf_fromstr_022() {
+        let conf = "net=10.192.0.24/29\ngateway=10.192.0.25\nname=test-bridge\n";
+        let uut = BridgeConf::from_str(&format!("{}reserved=10.192.0.26-10.192.0.27", conf));
+        assert!(uut.is_ok());
+        assert_eq!(
+            uut.unwrap().reserved,
+            vec!["10.192.0.26-10.192.0.27".parse().unwrap()]
+        );
+        // a broken reserved= reserves the whole network, the bridge is kept
+        let whole = vec!["10.192.0.25-10.192.0.30".parse().unwrap()];
+        let uut = BridgeConf::from_str(&format!("{}reserved=10.192.0.30-10.192.0.33", conf));
+        assert_eq!(uut.unwrap().reserved, whole);
+        let uut = BridgeConf::from_str(&format!("{}reserved=10.192.0", conf));
+        assert_eq!(uut.unwrap().reserved, whole);
+        let uut = analyze_bridge_conf(
+            Path::new("test-bridge"),
+            &format!("{}reserved=10.192.0", conf),
+        );
+        assert!(uut.has_errors());
+        assert_eq!(uut.diagnostics[0].key, Some("reserved".to_string()));
+        assert_eq!(uut.conf.unwrap().reserved, whole);
     }
 }
@@ -0,0 +1,365 @@
+use crate::util::parse_value;
+use crate::Result;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    /// A `[export ]key=value` line, with the value as written, quoted if needed;
+    /// `raw` is the original text, dropped when the value is modified
+    Entry {
+        key: String,
+        value: String,
+        export: bool,
+        raw: Option<String>,
+    },
+    /// Comments, empty lines and everything not recognized, kept verbatim
+    Other(String),
+}
+
+impl Line {
+    fn key(&self) -> Option<&str> {
+        match self {
+            Line::Entry { key, .. } => Some(key),
+            Line::Other(_) => None,
+        }
+    }
+
+    fn entry(key: &str, value: &str) -> Line {
+        Line::Entry {
+            key: key.to_string(),
+            value: shell_quote(value),
+            export: false,
+            raw: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Line::Entry { raw: Some(raw), .. } => write!(f, "{}", raw),
+            Line::Entry {
+                key,
+                value,
+                export,
+                raw: None,
+            } => {
+                let export = if *export { "export " } else { "" };
+                write!(f, "{}{}={}", export, key, value)
+            }
+            Line::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// An editable configuration file (pot.conf, bridge files)
+///
+/// Comments, key order and lines not understood are preserved:
+/// an unmodified document is written back byte by byte
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfDocument {
+    lines: Vec<Line>,
+    trailing_newline: bool,
+}
+
+impl ConfDocument {
+    pub fn new() -> Self {
+        ConfDocument {
+            lines: Vec::new(),
+            trailing_newline: true,
+        }
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let s = std::fs::read_to_string(path)?;
+        Ok(s.parse().unwrap())
+    }
+
+    /// Write the document, via a temporary file renamed over `path`
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        crate::util::write_atomic(path.as_ref(), self.to_string().as_bytes())
+    }
+
+    /// The value of the last occurrence of `key`, unquoted: the words up to a comment,
+    /// separated by a space, as PotConfig reads it
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.get_all(key).pop()
+    }
+
+    /// The values of all the occurrences of `key`, unquoted, in order
+    ///
+    /// A value with broken quoting is returned as written
+    pub fn get_all(&self, key: &str) -> Vec<String> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::Entry { key: k, value, .. } if k == key => {
+                    Some(parse_value(value).unwrap_or_else(|| value.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The keys, in order of first appearance
+    pub fn keys(&self) -> Vec<&str> {
+        let mut result: Vec<&str> = Vec::new();
+        for key in self.lines.iter().filter_map(Line::key) {
+            if !result.contains(&key) {
+                result.push(key);
+            }
+        }
+        result
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.lines.iter().any(|line| line.key() == Some(key))
+    }
+
+    /// Set the value of `key`, replacing the first occurrence and removing the others;
+    /// the value is quoted, if needed
+    ///
+    /// If `key` is not present, it's added at the end
+    pub fn set(&mut self, key: &str, value: &str) {
+        let mut found = false;
+        self.lines.retain(|line| {
+            if line.key() != Some(key) {
+                true
+            } else if !found {
+                found = true;
+                true
+            } else {
+                false
+            }
+        });
+        if let Some(line) = self.lines.iter_mut().find(|line| line.key() == Some(key)) {
+            if let Line::Entry { value: v, raw, .. } = line {
+                if parse_value(v).as_deref() != Some(value) {
+                    *v = shell_quote(value);
+                    *raw = None;
+                }
+            }
+        } else {
+            self.lines.push(Line::entry(key, value));
+        }
+    }
+
+    /// Remove all the occurrences of `key`, returning true if at least one was present
+    pub fn unset(&mut self, key: &str) -> bool {
+        let len = self.lines.len();
+        self.lines.retain(|line| line.key() != Some(key));
+        len != self.lines.len()
+    }
+
+    /// Add a new occurrence of `key`, after the last existing one or at the end;
+    /// the value is quoted, if needed
+    pub fn append(&mut self, key: &str, value: &str) {
+        match self.lines.iter().rposition(|line| line.key() == Some(key)) {
+            Some(index) => self.lines.insert(index + 1, Line::entry(key, value)),
+            None => self.lines.push(Line::entry(key, value)),
+        }
+    }
+}
+
+/// Quote a value, if needed, to be safely evaluated by /bin/sh
+pub fn shell_quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c))
+    {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+impl FromStr for ConfDocument {
+    type Err = std::convert::Infallible;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let lines = s
+            .lines()
+            .map(|linestr| {
+                let trimmed = linestr.trim();
+                if trimmed.starts_with('#') {
+                    return Line::Other(linestr.to_string());
+                }
+                let (export, assignment) = match trimmed.strip_prefix("export") {
+                    Some(rest) if rest.starts_with(char::is_whitespace) => {
+                        (true, rest.trim_start())
+                    }
+                    _ => (false, trimmed),
+                };
+                match assignment.split_once('=') {
+                    Some((key, value)) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+                        Line::Entry {
+                            key: key.to_string(),
+                            value: value.to_string(),
+                            export,
+                            raw: Some(linestr.to_string()),
+                        }
+                    }
+                    _ => Line::Other(linestr.to_string()),
+                }
+            })
+            .collect();
+        Ok(ConfDocument {
+            lines,
+            trailing_newline: s.is_empty() || s.ends_with('\n'),
+        })
+    }
+}
+
+impl std::fmt::Display for ConfDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", line)?;
+        }
+        if self.trailing_newline && !self.lines.is_empty() {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quote_001() {
+        assert_eq!(shell_quote("10.192.0.3"), "10.192.0.3");
+        assert_eq!(shell_quote("10.192.0.0/24"), "10.192.0.0/24");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's $HOME"), "'it'\\''s $HOME'");
+    }
+
+    const POT_CONF: &str = "# pot configuration\npot.level=1\n  ip=10.192.0.3 \n\
+                            pot.depend=dns\n\nsomething weird\npot.depend=db\nbridge=backend";
+
+    #[test]
+    fn conf_document_roundtrip_001() {
+        let uut = ConfDocument::from_str(POT_CONF).unwrap();
+        assert_eq!(uut.to_string(), POT_CONF);
+        let s = format!("{}\n", POT_CONF);
+        let uut = ConfDocument::from_str(&s).unwrap();
+        assert_eq!(uut.to_string(), s);
+        let uut = ConfDocument::from_str("").unwrap();
+        assert_eq!(uut.to_string(), "");
+    }
+
+    #[test]
+    fn conf_document_get_001() {
+        let uut = ConfDocument::from_str(POT_CONF).unwrap();
+        assert_eq!(uut.get("pot.level"), Some("1".to_string()));
+        assert_eq!(uut.get("ip"), Some("10.192.0.3".to_string()));
+        assert_eq!(uut.get("pot.depend"), Some("db".to_string()));
+        assert_eq!(uut.get_all("pot.depend"), vec!["dns", "db"]);
+        assert_eq!(uut.get("pot.cmd"), None);
+        assert_eq!(uut.keys(), vec!["pot.level", "ip", "pot.depend", "bridge"]);
+    }
+
+    #[test]
+    fn conf_document_set_001() {
+        let mut uut = ConfDocument::from_str(POT_CONF).unwrap();
+        uut.set("ip", "10.192.0.4");
+        uut.set("pot.level", "1");
+        uut.set("pot.cmd", "sh /etc/rc");
+        assert_eq!(
+            uut.to_string(),
+            "# pot configuration\npot.level=1\nip=10.192.0.4\npot.depend=dns\n\n\
+             something weird\npot.depend=db\nbridge=backend\npot.cmd='sh /etc/rc'"
+        );
+        uut.set("pot.depend", "web");
+        assert_eq!(uut.get_all("pot.depend"), vec!["web"]);
+    }
+
+    #[test]
+    fn conf_document_set_002() {
+        let mut uut = ConfDocument::from_str("host.hostname=\"web.pot\"\n").unwrap();
+        assert_eq!(uut.get("host.hostname"), Some("web.pot".to_string()));
+        // the same value, differently quoted, is not rewritten
+        uut.set("host.hostname", "web.pot");
+        assert_eq!(uut.to_string(), "host.hostname=\"web.pot\"\n");
+        for value in &["a b", "$HOME/it's", "", "a\\b \"c\""] {
+            uut.set("description", value);
+            let uut = ConfDocument::from_str(&uut.to_string()).unwrap();
+            assert_eq!(uut.get("description").as_deref(), Some(*value));
+        }
+        // values of several words, as pot writes them, are read and compared whole
+        let conf = "pot.cmd=sh /etc/rc # the command\n";
+        let mut uut = ConfDocument::from_str(conf).unwrap();
+        assert_eq!(uut.get("pot.cmd"), Some("sh /etc/rc".to_string()));
+        uut.set("pot.cmd", "sh /etc/rc");
+        assert_eq!(uut.to_string(), conf);
+        uut.set("pot.cmd", "sh /etc/rc.local");
+        let uut = ConfDocument::from_str(&uut.to_string()).unwrap();
+        assert_eq!(uut.get("pot.cmd"), Some("sh /etc/rc.local".to_string()));
+    }
+
+    #[test]
+    fn conf_document_export_001() {
+        let conf = "export POT_EXTIF=em0\nexportfoo=1\n";
+        let mut uut = ConfDocument::from_str(conf).unwrap();
+        assert_eq!(uut.keys(), vec!["POT_EXTIF", "exportfoo"]);
+        assert_eq!(uut.get("POT_EXTIF"), Some("em0".to_string()));
+        uut.set("POT_EXTIF", "em0");
+        assert_eq!(uut.to_string(), conf);
+        uut.set("POT_EXTIF", "vtnet0");
+        assert_eq!(uut.to_string(), "export POT_EXTIF=vtnet0\nexportfoo=1\n");
+    }
+
+    #[test]
+    fn conf_document_unset_001() {
+        let mut uut = ConfDocument::from_str(POT_CONF).unwrap();
+        assert!(uut.unset("pot.depend"));
+        assert!(!uut.unset("pot.depend"));
+        assert!(!uut.contains_key("pot.depend"));
+        assert_eq!(
+            uut.to_string(),
+            "# pot configuration\npot.level=1\n  ip=10.192.0.3 \n\nsomething weird\nbridge=backend"
+        );
+    }
+
+    #[test]
+    fn conf_document_append_001() {
+        let mut uut = ConfDocument::from_str(POT_CONF).unwrap();
+        uut.append("pot.depend", "web");
+        uut.append("pot.env", "FOO=bar");
+        assert_eq!(uut.get_all("pot.depend"), vec!["dns", "db", "web"]);
+        assert_eq!(
+            uut.to_string(),
+            "# pot configuration\npot.level=1\n  ip=10.192.0.3 \npot.depend=dns\n\n\
+             something weird\npot.depend=db\npot.depend=web\nbridge=backend\npot.env=FOO=bar"
+        );
+        uut.append("pot.env", "BAR=a b");
+        assert_eq!(uut.get_all("pot.env"), vec!["FOO=bar", "BAR=a b"]);
+    }
+
+    #[test]
+    fn conf_document_write_001() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pot.conf");
+        let mut uut = ConfDocument::new();
+        uut.set("name", "backend");
+        uut.set("net", "10.192.0.16/28");
+        uut.write(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "name=backend\nnet=10.192.0.16/28\n"
+        );
+        let mut uut = ConfDocument::read(&path).unwrap();
+        uut.set("gateway", "10.192.0.17");
+        uut.write(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "name=backend\nnet=10.192.0.16/28\ngateway=10.192.0.17\n"
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found while reading a configuration file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    /// The line number, starting from 1, if the problem is related to a specific line
+    pub line: Option<usize>,
+    pub key: Option<String>,
+    pub reason: String,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, file: &Path, reason: &str) -> Self {
+        Diagnostic {
+            severity,
+            file: file.to_path_buf(),
+            line: None,
+            key: None,
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn error(file: &Path, reason: &str) -> Self {
+        Diagnostic::new(Severity::Error, file, reason)
+    }
+
+    pub fn warning(file: &Path, reason: &str) -> Self {
+        Diagnostic::new(Severity::Warning, file, reason)
+    }
+
+    pub fn at(mut self, line: Option<usize>, key: &str) -> Self {
+        self.line = line;
+        self.key = Some(key.to_string());
+        self
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        write!(f, ": {}: ", self.severity)?;
+        if let Some(key) = &self.key {
+            write!(f, "{}: ", key)?;
+        }
+        write!(f, "{}", self.reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostic_display_001() {
+        let uut = Diagnostic::error(Path::new("/opt/pot/jails/foo/conf/pot.conf"), "missing");
+        assert_eq!(
+            uut.to_string(),
+            "/opt/pot/jails/foo/conf/pot.conf: error: missing"
+        );
+        let uut = Diagnostic::warning(Path::new("pot.conf"), "invalid address").at(Some(3), "ip");
+        assert_eq!(uut.to_string(), "pot.conf:3: warning: ip: invalid address");
+    }
+}
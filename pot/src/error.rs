@@ -4,6 +4,8 @@ use thiserror::Error;
 pub enum PotError {
     #[error("System configuration incomplete")]
     IncompleteSystemConf,
+    #[error("System configuration incomplete: {1} requires {0}")]
+    MissingSystemConfValue(&'static str, &'static str),
     #[error("Command {0} not found")]
     WhichError(String),
     #[error("Invalid UTF-8 string")]
@@ -12,8 +14,102 @@ pub enum PotError {
     PathError(String),
     #[error("Error during a file operation")]
     FileError(#[from] std::io::Error),
+    #[error("Command {0} failed to execute")]
+    CommandError(String),
     #[error("jls failed")]
     JlsError,
+    #[error("Invalid value {1} for {0}")]
+    ConfValueError(String, String),
+    #[error("Invalid variable expansion in the value of {0}")]
+    VarExpansionError(String),
+    #[error("Variable {0} is defined referencing itself")]
+    SelfReferenceError(String),
+    #[error("Malformed lease database at {0}")]
+    LeaseDbError(String),
+    #[error("Address {0} already leased to {1}")]
+    LeaseConflict(std::net::IpAddr, String),
     #[error("Invalid bridge configuration")]
     BridgeConfError,
+    #[error("bridge {0} not found")]
+    BridgeNotFound(String),
+    #[error("bridge {0} already exists")]
+    BridgeExists(String),
+    #[error("bridge {0} overlaps the bridge {1}")]
+    BridgeOverlap(String, String),
+    #[error("bridge {0} still used by {1}")]
+    BridgeInUse(String, String),
+    #[error("bridge {0} could be used by {1}, whose configuration can't be understood")]
+    BridgeMembersUnknown(String, String),
+    #[error("{1} of the bridge {0} outside the new network")]
+    BridgeMemberOutside(String, String),
+    #[error("Invalid bridge: {0}")]
+    InvalidBridge(String),
+    #[error("Invalid bridge name {0:?}")]
+    InvalidBridgeName(String),
+    #[error("Network {0} overlaps addresses already in use")]
+    NetworkInUse(ipnet::IpNet),
+    #[error("Address {0} already in use")]
+    AddressInUse(std::net::IpAddr),
+    #[error("Malformed reservations file at {0}")]
+    ReservationsError(String),
+    #[error("Several addresses of {1} reserved for {0}")]
+    DuplicateReservation(String, ipnet::IpNet),
+    #[error("Malformed quarantine database at {0}")]
+    QuarantineDbError(String),
+    #[error("Address {0} is in quarantine")]
+    AddressQuarantined(std::net::IpAddr),
+    #[error("Address {0} is reserved")]
+    AddressReserved(std::net::IpAddr),
+    #[error("Address {0} outside the network {1}")]
+    AddressOutsideNetwork(std::net::IpAddr, ipnet::IpNet),
+    #[error("No address available")]
+    NoAddressAvailable,
+    #[error("No IPv{0} network configured")]
+    PoolNotConfigured(u8),
+    #[error("No network available")]
+    NoSubnetAvailable,
+    #[error("A network with size {0} is too small")]
+    NetworkTooSmall(u128),
+}
+
+impl PotError {
+    /// A stable identifier of the error, for machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            PotError::IncompleteSystemConf => "incomplete-system-config",
+            PotError::MissingSystemConfValue(_, _) => "incomplete-system-config",
+            PotError::WhichError(_) => "pot-not-found",
+            PotError::Utf8Error(_) => "invalid-utf8",
+            PotError::PathError(_) => "invalid-path",
+            PotError::FileError(_) => "io-error",
+            PotError::CommandError(_) => "command-failed",
+            PotError::JlsError => "jls-failed",
+            PotError::ConfValueError(_, _) => "invalid-config-value",
+            PotError::VarExpansionError(_) => "invalid-variable-expansion",
+            PotError::SelfReferenceError(_) => "self-referencing-variable",
+            PotError::LeaseDbError(_) => "malformed-lease-db",
+            PotError::LeaseConflict(_, _) => "lease-conflict",
+            PotError::BridgeConfError => "invalid-bridge-config",
+            PotError::BridgeNotFound(_) => "bridge-not-found",
+            PotError::BridgeExists(_) => "bridge-exists",
+            PotError::BridgeOverlap(_, _) => "bridge-overlap",
+            PotError::BridgeInUse(_, _) => "bridge-in-use",
+            PotError::BridgeMembersUnknown(_, _) => "bridge-members-unknown",
+            PotError::BridgeMemberOutside(_, _) => "bridge-member-outside",
+            PotError::InvalidBridge(_) => "invalid-bridge",
+            PotError::InvalidBridgeName(_) => "invalid-bridge-name",
+            PotError::NetworkInUse(_) => "network-in-use",
+            PotError::AddressInUse(_) => "address-in-use",
+            PotError::ReservationsError(_) => "malformed-reservations",
+            PotError::DuplicateReservation(_, _) => "duplicate-reservation",
+            PotError::QuarantineDbError(_) => "malformed-quarantine-db",
+            PotError::AddressQuarantined(_) => "address-quarantined",
+            PotError::AddressReserved(_) => "address-reserved",
+            PotError::AddressOutsideNetwork(_, _) => "address-outside-network",
+            PotError::NoAddressAvailable => "no-address-available",
+            PotError::PoolNotConfigured(_) => "pool-not-configured",
+            PotError::NoSubnetAvailable => "no-subnet-available",
+            PotError::NetworkTooSmall(_) => "network-too-small",
+        }
+    }
 }
@@ -0,0 +1,1069 @@
+use crate::allocation::AllocationStrategy;
+use crate::bridge::{get_bridges_list, BridgeConf};
+use crate::error::PotError;
+use crate::iprange::{first_subnet_in, IpRange, IpRangeMap};
+use crate::lease::get_live_leases;
+use crate::potconf::IpStack;
+use crate::quarantine::get_quarantined;
+use crate::reservation::{get_reservations, Reservation};
+use crate::{get_pot_conf_list, NetType, NetworkPool, PotConf, PotSystemConfig, Result};
+use ipnet::IpNet;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Who, or what, an address belongs to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Owner {
+    /// The network address of a system pool
+    SystemNetwork,
+    /// The broadcast address of a system pool
+    SystemBroadcast,
+    SystemGateway,
+    /// The DNS pot
+    Dns {
+        name: String,
+    },
+    Pot {
+        name: String,
+        network_type: NetType,
+    },
+    /// An address reserved via potnet next --reserve
+    Lease {
+        owner: String,
+    },
+    BridgeNetwork {
+        bridge: String,
+    },
+    BridgeBroadcast {
+        bridge: String,
+    },
+    BridgeGateway {
+        bridge: String,
+    },
+    /// An address of the bridge network, not available in the main pool
+    BridgeRange {
+        bridge: String,
+    },
+    /// An address excluded from the allocation by the configuration
+    Reserved {
+        reason: String,
+    },
+    /// An address statically reserved for a pot, in the reservations file
+    Reservation {
+        pot: String,
+    },
+    /// An address recently released by a pot or a lease owner
+    Quarantined {
+        owner: String,
+    },
+}
+
+impl Owner {
+    /// A stable identifier of the kind of owner
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Owner::SystemNetwork => "system-network",
+            Owner::SystemBroadcast => "system-broadcast",
+            Owner::SystemGateway => "system-gateway",
+            Owner::Dns { .. } => "dns",
+            Owner::Pot { .. } => "pot",
+            Owner::Lease { .. } => "lease",
+            Owner::BridgeNetwork { .. } => "bridge-network",
+            Owner::BridgeBroadcast { .. } => "bridge-broadcast",
+            Owner::BridgeGateway { .. } => "bridge-gateway",
+            Owner::BridgeRange { .. } => "bridge-range",
+            Owner::Reserved { .. } => "reserved",
+            Owner::Reservation { .. } => "reservation",
+            Owner::Quarantined { .. } => "quarantined",
+        }
+    }
+
+    /// The bridge the address belongs to, if any
+    pub fn bridge(&self) -> Option<&str> {
+        match self {
+            Owner::BridgeNetwork { bridge }
+            | Owner::BridgeBroadcast { bridge }
+            | Owner::BridgeGateway { bridge }
+            | Owner::BridgeRange { bridge } => Some(bridge),
+            _ => None,
+        }
+    }
+
+    /// The name of the pot, bridge or lease owner, or the reason of the reservation
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Owner::SystemNetwork | Owner::SystemBroadcast | Owner::SystemGateway => None,
+            Owner::Dns { name } | Owner::Pot { name, .. } => Some(name),
+            Owner::Lease { owner } | Owner::Quarantined { owner } => Some(owner),
+            Owner::BridgeNetwork { bridge }
+            | Owner::BridgeBroadcast { bridge }
+            | Owner::BridgeGateway { bridge }
+            | Owner::BridgeRange { bridge } => Some(bridge),
+            Owner::Reserved { reason } => Some(reason),
+            Owner::Reservation { pot } => Some(pot),
+        }
+    }
+
+    // the DNS pot has a pot.conf too, with the DNS address
+    fn is_same_claimant(&self, other: &Owner) -> bool {
+        match (self, other) {
+            (Owner::Dns { name }, Owner::Pot { name: pot, .. })
+            | (Owner::Pot { name: pot, .. }, Owner::Dns { name }) => name == pot,
+            _ => self == other,
+        }
+    }
+}
+
+/// The description used by potnet show
+impl std::fmt::Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Owner::SystemNetwork | Owner::SystemBroadcast => Ok(()),
+            Owner::SystemGateway => write!(f, "default gateway"),
+            Owner::Dns { name } | Owner::Pot { name, .. } => write!(f, "{}", name),
+            Owner::Lease { owner } => write!(f, "reserved for {}", owner),
+            Owner::BridgeNetwork { bridge } => write!(f, "{} bridge - network ", bridge),
+            Owner::BridgeBroadcast { bridge } => write!(f, "{} bridge - broadcast ", bridge),
+            Owner::BridgeGateway { bridge } => write!(f, "{} bridge - gateway ", bridge),
+            Owner::BridgeRange { bridge } => write!(f, "{} bridge - allocated address", bridge),
+            Owner::Reserved { reason } => write!(f, "reserved ({})", reason),
+            Owner::Reservation { pot } => write!(f, "static address of {}", pot),
+            Owner::Quarantined { owner } => write!(f, "quarantined, released by {}", owner),
+        }
+    }
+}
+
+/// The database of the addresses already taken, in the pot network or in a bridge
+#[derive(Clone, Debug)]
+pub struct IpDb {
+    map: IpRangeMap<Owner>,
+    /// All the owners claiming an address via insert, in insertion order;
+    /// the map keeps only the last one
+    claims: BTreeMap<IpAddr, Vec<Owner>>,
+    /// The static reservations of the addresses in the database
+    reservations: Vec<Reservation>,
+}
+
+/// How a free subnet is chosen among the free blocks of addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fit {
+    /// The first subnet, by address
+    #[default]
+    First,
+    /// A subnet in the smallest free block big enough, to limit the fragmentation
+    Best,
+}
+
+impl FromStr for Fit {
+    type Err = PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Fit::First),
+            "best" => Ok(Fit::Best),
+            _ => Err(PotError::ConfValueError("fit".to_string(), s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Fit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Fit::First => "first",
+            Fit::Best => "best",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// The free addresses of a network, as ranges and as the minimal list of networks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreeSpace {
+    pub network: IpNet,
+    /// The ranges of free addresses, in ascending order
+    pub ranges: Vec<IpRange>,
+    /// The networks covering exactly the free ranges, in ascending order
+    pub nets: Vec<IpNet>,
+    /// The number of free addresses, saturated for the biggest IPv6 networks
+    pub addresses: u128,
+}
+
+impl FreeSpace {
+    /// The biggest free network, the first one among the networks of the same size
+    pub fn largest(&self) -> Option<IpNet> {
+        self.nets.iter().min_by_key(|net| net.prefix_len()).copied()
+    }
+
+    /// The share of the free addresses outside the biggest free network: 0 if all the free
+    /// addresses can be allocated as a single network, close to 1 if they are scattered
+    pub fn fragmentation(&self) -> f64 {
+        match self.largest() {
+            Some(largest) if self.addresses > 0 => {
+                let largest = IpRange::from_net(&largest).span() as f64 + 1.0;
+                1.0 - largest / self.addresses as f64
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+impl Default for IpDb {
+    fn default() -> Self {
+        IpDb::new()
+    }
+}
+
+impl IpDb {
+    pub fn new() -> Self {
+        IpDb {
+            map: IpRangeMap::new(),
+            claims: BTreeMap::new(),
+            reservations: Vec::new(),
+        }
+    }
+
+    /// The addresses taken in the pools of the system: network, gateway, DNS,
+    /// pots, live leases and the whole range of every bridge
+    pub fn load(conf: &PotSystemConfig) -> Result<Self> {
+        let mut ip_db = IpDb::new();
+        ip_db.insert(conf.network.network(), Owner::SystemNetwork);
+        ip_db.insert(conf.network.broadcast(), Owner::SystemBroadcast);
+        ip_db.insert(conf.gateway, Owner::SystemGateway);
+        ip_db.insert(
+            conf.dns_ip,
+            Owner::Dns {
+                name: conf.dns_name.clone(),
+            },
+        );
+        if let Some(pool6) = conf.pool6() {
+            ip_db.insert(pool6.network.network(), Owner::SystemNetwork);
+            ip_db.insert(pool6.gateway, Owner::SystemGateway);
+            if let Some(dns_ip6) = pool6.dns_ip {
+                ip_db.insert(
+                    dns_ip6,
+                    Owner::Dns {
+                        name: conf.dns_name.clone(),
+                    },
+                );
+            }
+        }
+        for v in &get_pot_conf_list(conf.clone()) {
+            if v.network_type == NetType::PublicBridge || v.network_type == NetType::PrivateBridge {
+                for ip in &v.ip_addrs {
+                    ip_db.insert(*ip, pot_owner(v));
+                }
+            }
+        }
+        for pool in conf.pools() {
+            ip_db.insert_leases(conf, &pool.network)?;
+            ip_db.insert_quarantined(conf, &pool.network)?;
+        }
+        // ranges outside the pools are reported by potnet config-check
+        let reserved: Vec<IpRange> = conf
+            .reserved
+            .iter()
+            .filter(|r| {
+                conf.pools()
+                    .iter()
+                    .any(|p| p.network.contains(&r.start) && p.network.contains(&r.end))
+            })
+            .copied()
+            .collect();
+        ip_db.insert_reserved(&reserved, "POT_NETWORK_RESERVED");
+        let reservations = get_reservations(conf)?;
+        ip_db.insert_reservations(reservations.iter().filter(|r| {
+            r.bridge.is_none() && conf.pools().iter().any(|p| p.network.contains(&r.ip))
+        }));
+        for b in &get_bridges_list(conf)? {
+            ip_db.insert_bridge(b);
+            ip_db.insert_reserved(&b.reserved, &format!("bridge {}", b.name));
+            ip_db.insert_reservations(reservations.iter().filter(|r| is_in_bridge(r, b)));
+            // add all the not yet allocated hosts
+            for network in b.networks() {
+                ip_db.fill_range(
+                    IpRange::hosts(network),
+                    Owner::BridgeRange {
+                        bridge: b.name.clone(),
+                    },
+                );
+            }
+        }
+        Ok(ip_db)
+    }
+
+    /// The addresses taken in the network of `bridge`
+    pub fn load_bridge(conf: &PotSystemConfig, bridge: &BridgeConf) -> Result<Self> {
+        let mut ip_db = IpDb::new();
+        ip_db.insert_bridge(bridge);
+        for v in &get_pot_conf_list(conf.clone()) {
+            if v.network_type == NetType::PublicBridge || v.network_type == NetType::PrivateBridge {
+                for ip in v.ip_addrs.iter().filter(|ip| bridge.network.contains(*ip)) {
+                    ip_db.insert(*ip, pot_owner(v));
+                }
+            }
+        }
+        ip_db.insert_leases(conf, &bridge.network)?;
+        ip_db.insert_quarantined(conf, &bridge.network)?;
+        ip_db.insert_reserved(&bridge.reserved, &format!("bridge {}", bridge.name));
+        ip_db.insert_reservations(
+            get_reservations(conf)?
+                .iter()
+                .filter(|r| is_in_bridge(r, bridge)),
+        );
+        Ok(ip_db)
+    }
+
+    // add the network, broadcast and gateway addresses of the bridge
+    fn insert_bridge(&mut self, bridge: &BridgeConf) {
+        let name = &bridge.name;
+        self.insert(
+            bridge.network.network(),
+            Owner::BridgeNetwork {
+                bridge: name.clone(),
+            },
+        );
+        self.insert(
+            bridge.network.broadcast(),
+            Owner::BridgeBroadcast {
+                bridge: name.clone(),
+            },
+        );
+        self.insert(
+            bridge.gateway,
+            Owner::BridgeGateway {
+                bridge: name.clone(),
+            },
+        );
+        if let Some(network6) = bridge.network6 {
+            self.insert(
+                network6.network(),
+                Owner::BridgeNetwork {
+                    bridge: name.clone(),
+                },
+            );
+        }
+        if let Some(gateway6) = bridge.gateway6 {
+            self.insert(
+                gateway6,
+                Owner::BridgeGateway {
+                    bridge: name.clone(),
+                },
+            );
+        }
+    }
+
+    // leases don't override the addresses already taken
+    fn insert_leases(&mut self, conf: &PotSystemConfig, network: &IpNet) -> Result<()> {
+        for lease in get_live_leases(conf)? {
+            if network.contains(&lease.ip) {
+                self.fill_range(
+                    IpRange::single(lease.ip),
+                    Owner::Lease {
+                        owner: lease.owner.clone(),
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // quarantined addresses don't override the addresses already taken, e.g. reused by a pot
+    fn insert_quarantined(&mut self, conf: &PotSystemConfig, network: &IpNet) -> Result<()> {
+        for q in get_quarantined(conf)? {
+            if network.contains(&q.ip) {
+                self.fill_range(
+                    IpRange::single(q.ip),
+                    Owner::Quarantined {
+                        owner: q.owner.clone(),
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // reserved ranges don't override the addresses already taken, e.g. by a pot
+    fn insert_reserved(&mut self, ranges: &[IpRange], reason: &str) {
+        for range in ranges {
+            self.fill_range(
+                *range,
+                Owner::Reserved {
+                    reason: reason.to_string(),
+                },
+            );
+        }
+    }
+
+    // a reserved address is labelled as such only if not taken yet, e.g. by its pot
+    fn insert_reservations<'a>(&mut self, reservations: impl Iterator<Item = &'a Reservation>) {
+        for r in reservations {
+            self.fill_range(
+                IpRange::single(r.ip),
+                Owner::Reservation { pot: r.pot.clone() },
+            );
+            self.reservations.push(r.clone());
+        }
+    }
+
+    /// Assign `ip` to `owner`, overriding the current one, that is still reported by claimants
+    pub fn insert(&mut self, ip: IpAddr, owner: Owner) {
+        let claims = self.claims.entry(ip).or_default();
+        if !claims.iter().any(|c| c.is_same_claimant(&owner)) {
+            claims.push(owner.clone());
+        }
+        self.map.insert(ip, owner);
+    }
+
+    /// All the owners that claimed `ip` via insert
+    pub fn claimants(&self, ip: &IpAddr) -> &[Owner] {
+        self.claims.get(ip).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The addresses claimed by more than one owner, with all of them, in ascending order
+    pub fn duplicates(&self) -> impl Iterator<Item = (IpAddr, &[Owner])> {
+        self.claims
+            .iter()
+            .filter(|(_, owners)| owners.len() > 1)
+            .map(|(ip, owners)| (*ip, owners.as_slice()))
+    }
+
+    /// Mark as taken the addresses of `range` that are still free
+    pub fn fill_range(&mut self, range: IpRange, owner: Owner) {
+        self.map.fill_range(range, owner);
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.map.contains(ip)
+    }
+
+    pub fn get(&self, ip: &IpAddr) -> Option<&Owner> {
+        self.map.get(ip)
+    }
+
+    /// The ranges of taken addresses, in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = (IpRange, &Owner)> {
+        self.map.iter()
+    }
+
+    /// The ranges of free addresses of `range`, in ascending order
+    pub fn free_ranges(&self, range: IpRange) -> Vec<IpRange> {
+        self.map.gaps(range)
+    }
+
+    /// The complement of the database in `network`
+    pub fn free_space(&self, network: &IpNet) -> FreeSpace {
+        let ranges = self.free_ranges(IpRange::from_net(network));
+        FreeSpace {
+            network: *network,
+            nets: ranges.iter().flat_map(IpRange::to_nets).collect(),
+            addresses: ranges.iter().fold(0u128, |sum, r| {
+                sum.saturating_add(r.span()).saturating_add(1)
+            }),
+            ranges,
+        }
+    }
+
+    /// The first free host address of `network`
+    pub fn next_free(&self, network: &IpNet) -> Option<IpAddr> {
+        self.map.first_free(&IpRange::hosts(network))
+    }
+
+    /// The first address of `range` not taken yet
+    pub fn first_free(&self, range: &IpRange) -> Option<IpAddr> {
+        self.map.first_free(range)
+    }
+
+    /// The last address of `range` not taken yet
+    pub fn last_free(&self, range: &IpRange) -> Option<IpAddr> {
+        self.map.last_free(range)
+    }
+
+    /// The address of `network` statically reserved for `pot`, if any
+    ///
+    /// It's an error if the address is taken by someone else, e.g. another pot,
+    /// or if several addresses of `network` are reserved for `pot`
+    pub fn reserved_for(&self, pot: &str, network: &IpNet) -> Result<Option<IpAddr>> {
+        let mut reserved = self
+            .reservations
+            .iter()
+            .filter(|r| r.pot == pot && network.contains(&r.ip))
+            .map(|r| r.ip);
+        let ip = match reserved.next() {
+            Some(ip) => ip,
+            None => return Ok(None),
+        };
+        if reserved.any(|other| other != ip) {
+            return Err(PotError::DuplicateReservation(pot.to_string(), *network));
+        }
+        match self.get(&ip) {
+            None => Ok(Some(ip)),
+            Some(Owner::Reservation { pot: name }) | Some(Owner::Pot { name, .. })
+                if name == pot =>
+            {
+                Ok(Some(ip))
+            }
+            Some(Owner::Lease { owner }) | Some(Owner::Quarantined { owner }) if owner == pot => {
+                Ok(Some(ip))
+            }
+            Some(_) => Err(PotError::AddressInUse(ip)),
+        }
+    }
+
+    /// The address of `network` for `pot`: its reserved one, if any,
+    /// or a free one chosen by `strategy`
+    pub fn next_for(
+        &self,
+        network: &IpNet,
+        pot: Option<&str>,
+        strategy: &dyn AllocationStrategy,
+    ) -> Result<IpAddr> {
+        if let Some(pot) = pot {
+            if let Some(ip) = self.reserved_for(pot, network)? {
+                return Ok(ip);
+            }
+        }
+        strategy
+            .pick(self, &IpRange::hosts(network), pot)
+            .ok_or(PotError::NoAddressAvailable)
+    }
+
+    /// An IPv4 address and an IPv6 one for `pot`: the reserved ones, if any, otherwise
+    /// free ones, where the IPv6 address has the same host part of the IPv4 one, if available
+    pub fn next_pair(
+        &self,
+        pool: &NetworkPool,
+        pool6: &NetworkPool,
+        pot: Option<&str>,
+        strategy: &dyn AllocationStrategy,
+    ) -> Result<(IpAddr, IpAddr)> {
+        let addr = self.next_for(&pool.network, pot, strategy)?;
+        if let Some(pot) = pot {
+            if let Some(addr6) = self.reserved_for(pot, &pool6.network)? {
+                return Ok((addr, addr6));
+            }
+        }
+        if let (IpAddr::V4(addr4), IpNet::V4(network), IpNet::V6(network6)) =
+            (addr, pool.network, pool6.network)
+        {
+            let host_part = u32::from(addr4) - u32::from(network.network());
+            let candidate = IpAddr::from(std::net::Ipv6Addr::from(
+                u128::from(network6.network()) | u128::from(host_part),
+            ));
+            if pool6.network.contains(&candidate) && !self.contains(&candidate) {
+                return Ok((addr, candidate));
+            }
+        }
+        Ok((addr, self.next_for(&pool6.network, pot, strategy)?))
+    }
+
+    /// The next addresses of the system pools for `pot`: one of the given family,
+    /// a pair for a dual-stack pot, one of the main pool if no family is given
+    pub fn next_in_pools(
+        &self,
+        conf: &PotSystemConfig,
+        family: Option<IpStack>,
+        pot: Option<&str>,
+        strategy: &dyn AllocationStrategy,
+    ) -> Result<Vec<IpAddr>> {
+        let addr = match family {
+            None => self.next_for(&conf.network, pot, strategy)?,
+            Some(IpStack::Ipv4) => self.next_for(&get_pool(conf, false)?.network, pot, strategy)?,
+            Some(IpStack::Ipv6) => self.next_for(&get_pool(conf, true)?.network, pot, strategy)?,
+            Some(IpStack::Dual) => {
+                let pool = get_pool(conf, false)?;
+                let pool6 = get_pool(conf, true)?;
+                let (addr, addr6) = self.next_pair(&pool, &pool6, pot, strategy)?;
+                return Ok(vec![addr, addr6]);
+            }
+        };
+        Ok(vec![addr])
+    }
+
+    /// Check that `ip` can be used by `pot` and is in the system pool of its family
+    pub fn validate(&self, conf: &PotSystemConfig, ip: IpAddr, pot: Option<&str>) -> Result<()> {
+        let network = conf
+            .pool_for(ip.is_ipv6())
+            .map(|pool| pool.network)
+            .unwrap_or(conf.network);
+        self.validate_in(&network, ip, pot)
+    }
+
+    /// Check that `ip` is free, not reserved except for `pot`, and in `network`
+    pub fn validate_in(&self, network: &IpNet, ip: IpAddr, pot: Option<&str>) -> Result<()> {
+        match self.get(&ip) {
+            Some(Owner::Reservation { pot: owner }) | Some(Owner::Quarantined { owner })
+                if Some(owner.as_str()) == pot => {}
+            Some(Owner::Quarantined { .. }) => return Err(PotError::AddressQuarantined(ip)),
+            Some(Owner::Reserved { .. }) | Some(Owner::Reservation { .. }) => {
+                return Err(PotError::AddressReserved(ip))
+            }
+            Some(_) => return Err(PotError::AddressInUse(ip)),
+            None => (),
+        }
+        if !network.contains(&ip) {
+            return Err(PotError::AddressOutsideNetwork(ip, *network));
+        }
+        Ok(())
+    }
+
+    pub fn is_subnet_usable(&self, subnet: &IpNet) -> bool {
+        !self.map.overlaps(&IpRange::from_net(subnet))
+    }
+
+    /// The first free subnet of `within` big enough for `host_number` hosts, gateway excluded
+    pub fn new_subnet(&self, within: &IpNet, host_number: u128) -> Result<IpNet> {
+        if host_number <= 1 {
+            return Err(PotError::NetworkTooSmall(host_number));
+        }
+        get_prefix_length(host_number, &within.addr())
+            .filter(|prefix_length| *prefix_length >= within.prefix_len())
+            .and_then(|prefix_length| self.map.first_free_subnet(within, prefix_length))
+            .ok_or(PotError::NoSubnetAvailable)
+    }
+
+    /// Pack the subnets for the host numbers of `requests`, by name, in the free space
+    /// of `within`, the biggest first; the subnets are returned in ascending order
+    ///
+    /// Every subnet is chosen as new_subnet does, ignoring the subnets already packed
+    pub fn plan_subnets(
+        &self,
+        within: &IpNet,
+        requests: &[(String, u128)],
+    ) -> Result<Vec<(String, IpNet)>> {
+        let mut requests = requests.to_vec();
+        requests.sort_by_key(|(_, host_number)| std::cmp::Reverse(*host_number));
+        let mut scratch = self.clone();
+        let mut result = Vec::new();
+        for (name, host_number) in requests {
+            let subnet = scratch.new_subnet(within, host_number)?;
+            scratch.fill_range(
+                IpRange::from_net(&subnet),
+                Owner::BridgeRange {
+                    bridge: name.clone(),
+                },
+            );
+            result.push((name, subnet));
+        }
+        result.sort_by_key(|(_, subnet)| *subnet);
+        Ok(result)
+    }
+
+    /// A free subnet of `within` with the given prefix length, chosen as `fit` says
+    pub fn new_subnet_with(&self, within: &IpNet, prefix_len: u8, fit: Fit) -> Result<IpNet> {
+        if prefix_len < within.prefix_len() || prefix_len > within.max_prefix_len() {
+            return Err(PotError::ConfValueError(
+                "prefix length".to_string(),
+                prefix_len.to_string(),
+            ));
+        }
+        let mut candidates = self
+            .map
+            .gaps(IpRange::from_net(within))
+            .into_iter()
+            .filter_map(|gap| first_subnet_in(&gap, prefix_len).map(|subnet| (gap, subnet)));
+        let result = match fit {
+            Fit::First => candidates.next(),
+            Fit::Best => candidates.min_by_key(|(gap, _)| gap.span()),
+        };
+        result
+            .map(|(_, subnet)| subnet)
+            .ok_or(PotError::NoSubnetAvailable)
+    }
+}
+
+fn is_in_bridge(reservation: &Reservation, bridge: &BridgeConf) -> bool {
+    reservation.bridge.as_deref() == Some(bridge.name.as_str())
+        && bridge.network.contains(&reservation.ip)
+}
+
+fn pot_owner(pot: &PotConf) -> Owner {
+    Owner::Pot {
+        name: pot.name.clone(),
+        network_type: pot.network_type,
+    }
+}
+
+fn get_pool(conf: &PotSystemConfig, ipv6: bool) -> Result<NetworkPool> {
+    conf.pool_for(ipv6)
+        .ok_or(PotError::PoolNotConfigured(if ipv6 { 6 } else { 4 }))
+}
+
+/// The number of host bits needed for `host_number` hosts, plus network and broadcast
+pub fn get_network_size(host_number: u128) -> Option<u8> {
+    if host_number == 0 {
+        return None;
+    }
+    (2..=128).find(|bits| {
+        // the capacity of a /0 doesn't fit in a u128
+        let max_hosts = if *bits == 128 {
+            u128::MAX - 1
+        } else {
+            (1u128 << bits) - 2
+        };
+        host_number <= max_hosts
+    })
+}
+
+/// The prefix length of a network with `host_number` hosts, in the family of `ip_addr`;
+/// None if the family is too small
+pub fn get_prefix_length(host_number: u128, ip_addr: &IpAddr) -> Option<u8> {
+    let max_length: u8 = match ip_addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    get_network_size(host_number).and_then(|network_size| max_length.checked_sub(network_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocation::LowestFirst;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn pot(name: &str) -> Owner {
+        Owner::Pot {
+            name: name.to_string(),
+            network_type: NetType::PublicBridge,
+        }
+    }
+
+    #[test]
+    fn get_network_size_000() {
+        let uut = get_network_size(2);
+        assert_eq!(uut, Some(2));
+    }
+    #[test]
+    fn get_network_size_001() {
+        let uut = get_network_size(5);
+        assert_eq!(uut, Some(3));
+    }
+    #[test]
+    fn get_network_size_002() {
+        let uut = get_network_size(7);
+        assert_eq!(uut, Some(4));
+    }
+    #[test]
+    fn get_network_size_003() {
+        assert_eq!(get_network_size(0), None);
+        assert_eq!(get_network_size(65535), Some(17));
+        assert_eq!(get_network_size(1 << 64), Some(65));
+        assert_eq!(get_network_size(u128::MAX - 1), Some(128));
+        assert_eq!(get_network_size(u128::MAX), None);
+    }
+
+    #[test]
+    fn get_prefix_length_000() {
+        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let uut = get_prefix_length(2, &ip_addr);
+        assert_eq!(uut, Some(30));
+    }
+    #[test]
+    fn get_prefix_length_001() {
+        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let uut = get_prefix_length(5, &ip_addr);
+        assert_eq!(uut, Some(29));
+    }
+    #[test]
+    fn get_prefix_length_002() {
+        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let uut = get_prefix_length(9, &ip_addr);
+        assert_eq!(uut, Some(28));
+    }
+    #[test]
+    fn get_prefix_length_010() {
+        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
+        let uut = get_prefix_length(2, &ip_addr);
+        assert_eq!(uut, Some(126));
+    }
+    #[test]
+    fn get_prefix_length_011() {
+        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
+        let uut = get_prefix_length(5, &ip_addr);
+        assert_eq!(uut, Some(125));
+    }
+    #[test]
+    fn get_prefix_length_012() {
+        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
+        let uut = get_prefix_length(9, &ip_addr);
+        assert_eq!(uut, Some(124));
+    }
+    #[test]
+    fn get_prefix_length_020() {
+        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        assert_eq!(get_prefix_length(1 << 32, &ip_addr), None);
+        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
+        assert_eq!(get_prefix_length((1 << 64) - 2, &ip_addr), Some(64));
+    }
+
+    #[test]
+    fn owner_001() {
+        let uut = Owner::BridgeGateway {
+            bridge: "backend".to_string(),
+        };
+        assert_eq!(uut.to_string(), "backend bridge - gateway ");
+        assert_eq!(uut.kind(), "bridge-gateway");
+        assert_eq!(uut.bridge(), Some("backend"));
+        let uut = Owner::SystemNetwork;
+        assert_eq!(uut.to_string(), "");
+        assert_eq!(uut.bridge(), None);
+    }
+
+    #[test]
+    fn ip_db_next_pair_001() {
+        let pool = NetworkPool {
+            network: "10.192.0.0/24".parse().unwrap(),
+            gateway: "10.192.0.1".parse().unwrap(),
+            dns_ip: None,
+        };
+        let pool6 = NetworkPool {
+            network: "fd00:1::/64".parse().unwrap(),
+            gateway: "fd00:1::1".parse().unwrap(),
+            dns_ip: None,
+        };
+        let mut uut = IpDb::new();
+        for i in 0..5 {
+            uut.insert(IpAddr::V4(Ipv4Addr::new(10, 192, 0, i)), pot("foo"));
+        }
+        let addr = "10.192.0.5".parse().unwrap();
+        assert_eq!(
+            uut.next_pair(&pool, &pool6, None, &LowestFirst).ok(),
+            Some((addr, "fd00:1::5".parse().unwrap()))
+        );
+        uut.insert("fd00:1::5".parse().unwrap(), pot("foo"));
+        uut.insert(pool6.gateway, Owner::SystemGateway);
+        // the Subnet-Router anycast address is never handed out
+        assert_eq!(
+            uut.next_pair(&pool, &pool6, None, &LowestFirst).ok(),
+            Some((addr, "fd00:1::2".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn ip_db_validate_001() {
+        let network = "10.192.0.0/24".parse().unwrap();
+        let mut uut = IpDb::new();
+        uut.insert("10.192.0.3".parse().unwrap(), pot("web"));
+        uut.insert_reserved(
+            &["10.192.0.10-10.192.0.20".parse().unwrap()],
+            "POT_NETWORK_RESERVED",
+        );
+        assert!(matches!(
+            uut.validate_in(&network, "10.192.0.12".parse().unwrap(), None),
+            Err(PotError::AddressReserved(_))
+        ));
+        assert!(uut
+            .validate_in(&network, "10.192.0.4".parse().unwrap(), None)
+            .is_ok());
+        assert!(uut
+            .validate_in(&network, "10.192.0.3".parse().unwrap(), None)
+            .is_err());
+        assert!(uut
+            .validate_in(&network, "10.192.1.3".parse().unwrap(), None)
+            .is_err());
+    }
+
+    #[test]
+    fn ip_db_reservation_001() {
+        let network = "10.192.0.0/24".parse().unwrap();
+        let reservations = [
+            Reservation {
+                pot: "web".to_string(),
+                ip: "10.192.0.10".parse().unwrap(),
+                bridge: None,
+            },
+            Reservation {
+                pot: "db".to_string(),
+                ip: "10.192.0.3".parse().unwrap(),
+                bridge: None,
+            },
+        ];
+        let mut uut = IpDb::new();
+        uut.insert("10.192.0.3".parse().unwrap(), pot("cache"));
+        uut.insert_reservations(reservations.iter());
+        let ip = "10.192.0.10".parse().unwrap();
+        assert_eq!(
+            uut.next_for(&network, Some("web"), &LowestFirst).ok(),
+            Some(ip)
+        );
+        assert_eq!(
+            uut.next_for(&network, None, &LowestFirst).ok(),
+            Some("10.192.0.1".parse().unwrap())
+        );
+        assert!(uut.next_for(&network, Some("db"), &LowestFirst).is_err());
+        assert!(uut.validate_in(&network, ip, Some("web")).is_ok());
+        assert!(matches!(
+            uut.validate_in(&network, ip, Some("db")),
+            Err(PotError::AddressReserved(_))
+        ));
+        assert!(uut.validate_in(&network, ip, None).is_err());
+        let duplicate = Reservation {
+            pot: "web".to_string(),
+            ip: "10.192.0.11".parse().unwrap(),
+            bridge: None,
+        };
+        uut.insert_reservations(std::iter::once(&duplicate));
+        assert!(matches!(
+            uut.next_for(&network, Some("web"), &LowestFirst),
+            Err(PotError::DuplicateReservation(_, _))
+        ));
+    }
+
+    #[test]
+    fn ip_db_duplicates_001() {
+        let mut uut = IpDb::new();
+        let dns = "10.192.0.2".parse().unwrap();
+        let web = "10.192.0.3".parse().unwrap();
+        uut.insert(
+            dns,
+            Owner::Dns {
+                name: "dns".to_string(),
+            },
+        );
+        uut.insert(dns, pot("dns"));
+        uut.insert(web, pot("web"));
+        uut.insert(web, pot("restored"));
+        uut.insert(web, pot("web"));
+        assert_eq!(uut.claimants(&dns).len(), 1);
+        assert_eq!(uut.get(&web), Some(&pot("web")));
+        let duplicates: Vec<(IpAddr, &[Owner])> = uut.duplicates().collect();
+        assert_eq!(duplicates, vec![(web, &[pot("web"), pot("restored")][..])]);
+        assert!(uut.claimants(&"10.192.0.4".parse().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn ip_db_quarantine_001() {
+        let network = "10.192.0.0/24".parse().unwrap();
+        let ip = "10.192.0.1".parse().unwrap();
+        let mut uut = IpDb::new();
+        uut.fill_range(
+            IpRange::single(ip),
+            Owner::Quarantined {
+                owner: "web".to_string(),
+            },
+        );
+        assert_eq!(
+            uut.next_for(&network, None, &LowestFirst).ok(),
+            Some("10.192.0.2".parse().unwrap())
+        );
+        assert!(uut.validate_in(&network, ip, Some("web")).is_ok());
+        assert!(matches!(
+            uut.validate_in(&network, ip, None),
+            Err(PotError::AddressQuarantined(_))
+        ));
+        // a pot already using the address keeps its label
+        uut.insert("10.192.0.2".parse().unwrap(), pot("db"));
+        uut.fill_range(
+            IpRange::single("10.192.0.2".parse().unwrap()),
+            Owner::Quarantined {
+                owner: "web".to_string(),
+            },
+        );
+        assert_eq!(uut.get(&"10.192.0.2".parse().unwrap()), Some(&pot("db")));
+    }
+
+    #[test]
+    fn ip_db_new_subnet_001() {
+        let within = "10.192.0.0/24".parse().unwrap();
+        let mut uut = IpDb::new();
+        uut.fill_range(
+            IpRange::new("10.192.0.0".parse().unwrap(), "10.192.0.9".parse().unwrap()).unwrap(),
+            pot("web"),
+        );
+        let subnet = uut.new_subnet(&within, 5).unwrap();
+        assert_eq!(subnet, "10.192.0.16/29".parse::<IpNet>().unwrap());
+        assert!(uut.is_subnet_usable(&subnet));
+        assert!(!uut.is_subnet_usable(&"10.192.0.8/29".parse().unwrap()));
+        assert!(uut.new_subnet(&within, 1).is_err());
+        assert!(uut.new_subnet(&within, 300).is_err());
+    }
+
+    #[test]
+    fn ip_db_new_subnet_002() {
+        let within = "10.192.0.0/24".parse().unwrap();
+        let mut uut = IpDb::new();
+        let range = |start: &str, end: &str| {
+            IpRange::new(start.parse().unwrap(), end.parse().unwrap()).unwrap()
+        };
+        // free blocks: 10.192.0.10-10.192.0.127 and 10.192.0.144-10.192.0.175
+        uut.fill_range(range("10.192.0.0", "10.192.0.9"), pot("web"));
+        uut.fill_range(range("10.192.0.128", "10.192.0.143"), pot("db"));
+        uut.fill_range(range("10.192.0.176", "10.192.0.255"), pot("www"));
+        assert_eq!(
+            uut.new_subnet_with(&within, 28, Fit::First).unwrap(),
+            "10.192.0.16/28".parse::<IpNet>().unwrap()
+        );
+        assert_eq!(
+            uut.new_subnet_with(&within, 28, Fit::Best).unwrap(),
+            "10.192.0.144/28".parse::<IpNet>().unwrap()
+        );
+        assert_eq!(
+            uut.new_subnet_with(&within, 26, Fit::Best).unwrap(),
+            "10.192.0.64/26".parse::<IpNet>().unwrap()
+        );
+        assert!(uut.new_subnet_with(&within, 25, Fit::Best).is_err());
+        assert!(uut.new_subnet_with(&within, 23, Fit::First).is_err());
+        assert!(uut.new_subnet_with(&within, 33, Fit::First).is_err());
+        assert_eq!("best".parse::<Fit>().ok(), Some(Fit::Best));
+        assert!("worst".parse::<Fit>().is_err());
+        assert_eq!(
+            uut.free_ranges(IpRange::from_net(&within)),
+            vec![
+                range("10.192.0.10", "10.192.0.127"),
+                range("10.192.0.144", "10.192.0.175")
+            ]
+        );
+    }
+
+    #[test]
+    fn ip_db_free_space_001() {
+        let network: IpNet = "10.192.0.0/24".parse().unwrap();
+        let mut uut = IpDb::new();
+        let range = |start: &str, end: &str| {
+            IpRange::new(start.parse().unwrap(), end.parse().unwrap()).unwrap()
+        };
+        uut.fill_range(range("10.192.0.0", "10.192.0.63"), pot("web"));
+        uut.fill_range(range("10.192.0.128", "10.192.0.255"), pot("db"));
+        let free = uut.free_space(&network);
+        assert_eq!(free.ranges, vec![range("10.192.0.64", "10.192.0.127")]);
+        assert_eq!(free.nets, vec!["10.192.0.64/26".parse::<IpNet>().unwrap()]);
+        assert_eq!(free.addresses, 64);
+        assert_eq!(free.largest(), Some("10.192.0.64/26".parse().unwrap()));
+        assert_eq!(free.fragmentation(), 0.0);
+        uut.insert("10.192.0.64".parse().unwrap(), pot("www"));
+        let free = uut.free_space(&network);
+        assert_eq!(free.nets.len(), 6);
+        assert_eq!(free.addresses, 63);
+        assert_eq!(free.largest(), Some("10.192.0.96/27".parse().unwrap()));
+        assert!((free.fragmentation() - 31.0 / 63.0).abs() < 1e-9);
+        uut.fill_range(range("10.192.0.64", "10.192.0.127"), pot("www"));
+        let free = uut.free_space(&network);
+        assert_eq!(free.largest(), None);
+        assert_eq!(free.fragmentation(), 0.0);
+    }
+
+    #[test]
+    fn ip_db_plan_subnets_001() {
+        let within = "10.192.0.0/24".parse().unwrap();
+        let mut uut = IpDb::new();
+        uut.fill_range(
+            IpRange::new("10.192.0.0".parse().unwrap(), "10.192.0.9".parse().unwrap()).unwrap(),
+            pot("web"),
+        );
+        let requests = vec![
+            ("db".to_string(), 6),
+            ("web".to_string(), 30),
+            ("cache".to_string(), 14),
+        ];
+        let plan = uut.plan_subnets(&within, &requests).unwrap();
+        let expected: Vec<(String, IpNet)> = vec![
+            ("cache".to_string(), "10.192.0.16/28".parse().unwrap()),
+            ("web".to_string(), "10.192.0.32/27".parse().unwrap()),
+            ("db".to_string(), "10.192.0.64/29".parse().unwrap()),
+        ];
+        assert_eq!(plan, expected);
+        assert!(plan.iter().all(|(_, subnet)| uut.is_subnet_usable(subnet)));
+        assert!(uut
+            .plan_subnets(&within, &[("big".to_string(), 200)])
+            .is_err());
+    }
+}
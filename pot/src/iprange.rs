@@ -0,0 +1,643 @@
+use crate::error::PotError;
+use crate::Result;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// An inclusive range of addresses of the same family
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpRange {
+    pub start: IpAddr,
+    pub end: IpAddr,
+}
+
+impl IpRange {
+    /// None if the addresses belong to different families or `start` is greater than `end`
+    pub fn new(start: IpAddr, end: IpAddr) -> Option<Self> {
+        if start.is_ipv4() != end.is_ipv4() || start > end {
+            return None;
+        }
+        Some(IpRange { start, end })
+    }
+
+    pub fn single(ip: IpAddr) -> Self {
+        IpRange { start: ip, end: ip }
+    }
+
+    /// All the addresses of the network, network and broadcast included
+    pub fn from_net(net: &IpNet) -> Self {
+        IpRange {
+            start: net.network(),
+            end: net.broadcast(),
+        }
+    }
+
+    /// The addresses usable by hosts
+    ///
+    /// As IpNet::hosts() does for IPv4; for IPv6, the Subnet-Router anycast address
+    /// (the network address) is excluded too
+    pub fn hosts(net: &IpNet) -> Self {
+        match net {
+            IpNet::V4(n) if n.prefix_len() < 31 => IpRange {
+                start: IpAddr::V4(Ipv4Addr::from(u32::from(n.network()) + 1)),
+                end: IpAddr::V4(Ipv4Addr::from(u32::from(n.broadcast()) - 1)),
+            },
+            IpNet::V6(n) if n.prefix_len() < 127 => IpRange {
+                start: IpAddr::V6(Ipv6Addr::from(u128::from(n.network()) + 1)),
+                end: IpAddr::V6(n.broadcast()),
+            },
+            _ => IpRange::from_net(net),
+        }
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.start <= *ip && *ip <= self.end
+    }
+
+    pub fn overlaps(&self, other: &IpRange) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    /// The number of addresses of the range, minus one, so that it doesn't overflow
+    pub fn span(&self) -> u128 {
+        to_bits(self.end) - to_bits(self.start)
+    }
+
+    /// The minimal list of networks covering exactly the range, in ascending order
+    pub fn to_nets(&self) -> Vec<IpNet> {
+        let ipv6 = self.start.is_ipv6();
+        let max_prefix_len: u8 = if ipv6 { 128 } else { 32 };
+        let end = to_bits(self.end);
+        let mut start = to_bits(self.start);
+        let mut result = Vec::new();
+        loop {
+            // the biggest network aligned at `start` not going beyond `end`
+            let mut host_bits = start.trailing_zeros().min(u32::from(max_prefix_len));
+            while start
+                .checked_add(host_mask(host_bits))
+                .filter(|last| *last <= end)
+                .is_none()
+            {
+                host_bits -= 1;
+            }
+            let last = start + host_mask(host_bits);
+            result.extend(new_net(
+                from_bits(start, ipv6),
+                max_prefix_len - host_bits as u8,
+            ));
+            if last == end {
+                return result;
+            }
+            start = last + 1;
+        }
+    }
+
+    /// The address at `offset` from the start, modulo the size of the range
+    pub fn at_offset(&self, offset: u128) -> IpAddr {
+        let span = self.span();
+        let offset = if span == u128::MAX {
+            offset
+        } else {
+            offset % (span + 1)
+        };
+        from_bits(to_bits(self.start) + offset, self.start.is_ipv6())
+    }
+}
+
+impl std::fmt::Display for IpRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_single() {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Parse an address, a network in CIDR notation or two addresses separated by `-`
+impl FromStr for IpRange {
+    type Err = PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let error = || PotError::ConfValueError("address range".to_string(), s.to_string());
+        let s = s.trim();
+        if let Some((start, end)) = s.split_once('-') {
+            let start = start.trim().parse().map_err(|_| error())?;
+            let end = end.trim().parse().map_err(|_| error())?;
+            IpRange::new(start, end).ok_or_else(error)
+        } else if s.contains('/') {
+            let net: IpNet = s.parse().map_err(|_| error())?;
+            Ok(IpRange::from_net(&net))
+        } else {
+            Ok(IpRange::single(s.parse().map_err(|_| error())?))
+        }
+    }
+}
+
+/// Parse a comma separated list of ranges, as accepted by IpRange::from_str
+pub fn parse_range_list(s: &str) -> Result<Vec<IpRange>> {
+    s.split(',')
+        .filter(|x| !x.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+fn to_bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(a) => u128::from(u32::from(a)),
+        IpAddr::V6(a) => u128::from(a),
+    }
+}
+
+// the bits are expected to fit the family
+fn from_bits(bits: u128, ipv6: bool) -> IpAddr {
+    if ipv6 {
+        IpAddr::V6(Ipv6Addr::from(bits))
+    } else {
+        IpAddr::V4(Ipv4Addr::from(bits as u32))
+    }
+}
+
+fn max_bits(ipv6: bool) -> u128 {
+    if ipv6 {
+        u128::MAX
+    } else {
+        u128::from(u32::MAX)
+    }
+}
+
+/// The network `ip`/`prefix_len`, None if the prefix length is not valid for the family
+pub fn new_net(ip: IpAddr, prefix_len: u8) -> Option<IpNet> {
+    match ip {
+        IpAddr::V4(a) => Ipv4Net::new(a, prefix_len).ok().map(IpNet::V4),
+        IpAddr::V6(a) => Ipv6Net::new(a, prefix_len).ok().map(IpNet::V6),
+    }
+}
+
+/// The first network with the given prefix length entirely contained in `range`
+pub fn first_subnet_in(range: &IpRange, prefix_len: u8) -> Option<IpNet> {
+    let ipv6 = range.start.is_ipv6();
+    let max_prefix_len: u8 = if ipv6 { 128 } else { 32 };
+    let mask = host_mask(u32::from(max_prefix_len.checked_sub(prefix_len)?));
+    let start = to_bits(range.start).checked_add(mask)? & !mask;
+    let end = start.checked_add(mask)?;
+    if end > to_bits(range.end) {
+        return None;
+    }
+    new_net(from_bits(start, ipv6), prefix_len)
+}
+
+// size - 1 of a network with `host_bits` host bits, to avoid the overflow of a /0
+fn host_mask(host_bits: u32) -> u128 {
+    if host_bits == 128 {
+        u128::MAX
+    } else {
+        (1u128 << host_bits) - 1
+    }
+}
+
+/// The address following `ip`, None at the end of the address space
+pub fn next_addr(ip: IpAddr) -> Option<IpAddr> {
+    let bits = to_bits(ip);
+    if bits == max_bits(ip.is_ipv6()) {
+        None
+    } else {
+        Some(from_bits(bits + 1, ip.is_ipv6()))
+    }
+}
+
+/// The address preceding `ip`, None at the beginning of the address space
+pub fn prev_addr(ip: IpAddr) -> Option<IpAddr> {
+    Some(from_bits(to_bits(ip).checked_sub(1)?, ip.is_ipv6()))
+}
+
+/// A map from disjoint address ranges to values
+///
+/// Lookups, the search of a free address and overlap checks are O(log n),
+/// whatever the size of the ranges, so that IPv6 networks can be handled
+#[derive(Debug, Clone, Default)]
+pub struct IpRangeMap<V> {
+    /// The ranges, indexed by their first address
+    entries: BTreeMap<IpAddr, (IpAddr, V)>,
+    /// The union of the ranges, where adjacent ranges are merged
+    occupied: BTreeMap<IpAddr, IpAddr>,
+}
+
+impl<V: Clone> IpRangeMap<V> {
+    pub fn new() -> Self {
+        IpRangeMap {
+            entries: BTreeMap::new(),
+            occupied: BTreeMap::new(),
+        }
+    }
+
+    /// The number of ranges
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Assign `value` to a single address, replacing the previous value, if any
+    pub fn insert(&mut self, ip: IpAddr, value: V) {
+        self.insert_range(IpRange::single(ip), value);
+    }
+
+    /// Assign `value` to all the addresses in `range`, replacing the previous values
+    pub fn insert_range(&mut self, range: IpRange, value: V) {
+        let overlapping: Vec<IpAddr> = self
+            .entries
+            .range(..=range.end)
+            .rev()
+            .take_while(|(_, (end, _))| *end >= range.start)
+            .map(|(start, _)| *start)
+            .collect();
+        for start in overlapping {
+            let (end, old_value) = self.entries.remove(&start).unwrap();
+            if start < range.start {
+                let before = prev_addr(range.start).unwrap();
+                self.entries.insert(start, (before, old_value.clone()));
+            }
+            if end > range.end {
+                let after = next_addr(range.end).unwrap();
+                self.entries.insert(after, (end, old_value));
+            }
+        }
+        self.entries.insert(range.start, (range.end, value));
+        self.occupy(range);
+    }
+
+    /// Assign `value` to the addresses in `range` not assigned yet
+    pub fn fill_range(&mut self, range: IpRange, value: V) {
+        for gap in self.gaps(range) {
+            self.entries.insert(gap.start, (gap.end, value.clone()));
+            self.occupy(gap);
+        }
+    }
+
+    pub fn get(&self, ip: &IpAddr) -> Option<&V> {
+        self.get_range(ip).map(|(_, value)| value)
+    }
+
+    /// The range containing `ip`, with its value
+    pub fn get_range(&self, ip: &IpAddr) -> Option<(IpRange, &V)> {
+        let (start, (end, value)) = self.entries.range(..=*ip).next_back()?;
+        if end >= ip {
+            Some((
+                IpRange {
+                    start: *start,
+                    end: *end,
+                },
+                value,
+            ))
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.occupied_range(ip).is_some()
+    }
+
+    /// True if at least one address in `range` is assigned
+    pub fn overlaps(&self, range: &IpRange) -> bool {
+        self.last_occupied_before(&range.end)
+            .map(|r| r.end >= range.start)
+            .unwrap_or(false)
+    }
+
+    /// The first address in `range` not assigned yet
+    pub fn first_free(&self, range: &IpRange) -> Option<IpAddr> {
+        let candidate = match self.occupied_range(&range.start) {
+            Some(r) => next_addr(r.end)?,
+            None => range.start,
+        };
+        if range.contains(&candidate) {
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+
+    /// The last address in `range` not assigned yet
+    pub fn last_free(&self, range: &IpRange) -> Option<IpAddr> {
+        let candidate = match self.occupied_range(&range.end) {
+            Some(r) => prev_addr(r.start)?,
+            None => range.end,
+        };
+        if range.contains(&candidate) {
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+
+    /// The first subnet of `within` with the given prefix length, that doesn't contain
+    /// any assigned address
+    pub fn first_free_subnet(&self, within: &IpNet, prefix_len: u8) -> Option<IpNet> {
+        if prefix_len < within.prefix_len() || prefix_len > within.max_prefix_len() {
+            return None;
+        }
+        let ipv6 = within.network().is_ipv6();
+        let mask = host_mask(u32::from(within.max_prefix_len() - prefix_len));
+        let last = to_bits(within.broadcast());
+        let mut start = to_bits(within.network());
+        loop {
+            let end = start.checked_add(mask)?;
+            if end > last {
+                return None;
+            }
+            let subnet = IpRange {
+                start: from_bits(start, ipv6),
+                end: from_bits(end, ipv6),
+            };
+            match self.last_occupied_before(&subnet.end) {
+                Some(r) if r.end >= subnet.start => {
+                    // skip to the first aligned subnet after the occupied range
+                    let next = to_bits(r.end).checked_add(1)?;
+                    start = next.checked_add(mask)? & !mask;
+                }
+                _ => return new_net(subnet.start, prefix_len),
+            }
+        }
+    }
+
+    /// The ranges, ordered by address, with their values
+    pub fn iter(&self) -> impl Iterator<Item = (IpRange, &V)> {
+        self.entries.iter().map(|(start, (end, value))| {
+            (
+                IpRange {
+                    start: *start,
+                    end: *end,
+                },
+                value,
+            )
+        })
+    }
+
+    /// The ranges of addresses in `range` not assigned yet
+    pub fn gaps(&self, range: IpRange) -> Vec<IpRange> {
+        let mut result = Vec::new();
+        let mut cursor = Some(range.start);
+        while let Some(start) = cursor.filter(|c| *c <= range.end) {
+            if let Some(r) = self.occupied_range(&start) {
+                cursor = next_addr(r.end);
+                continue;
+            }
+            let end = match self.occupied.range(start..).next() {
+                Some((next_start, _)) if *next_start <= range.end => {
+                    prev_addr(*next_start).unwrap()
+                }
+                _ => range.end,
+            };
+            result.push(IpRange { start, end });
+            cursor = next_addr(end);
+        }
+        result
+    }
+
+    fn occupied_range(&self, ip: &IpAddr) -> Option<IpRange> {
+        self.last_occupied_before(ip).filter(|r| r.end >= *ip)
+    }
+
+    fn last_occupied_before(&self, ip: &IpAddr) -> Option<IpRange> {
+        self.occupied
+            .range(..=*ip)
+            .next_back()
+            .map(|(start, end)| IpRange {
+                start: *start,
+                end: *end,
+            })
+    }
+
+    // add the range to the union, merging it with the overlapping or adjacent ones
+    fn occupy(&mut self, range: IpRange) {
+        let mut start = range.start;
+        let mut end = range.end;
+        if let Some(prev) = self.last_occupied_before(&start) {
+            if prev.end >= start || next_addr(prev.end) == Some(start) {
+                self.occupied.remove(&prev.start);
+                start = prev.start;
+                end = end.max(prev.end);
+            }
+        }
+        while let Some((next_start, next_end)) =
+            self.occupied.range(start..).next().map(|(s, e)| (*s, *e))
+        {
+            if next_start <= end || next_addr(end) == Some(next_start) {
+                self.occupied.remove(&next_start);
+                end = end.max(next_end);
+            } else {
+                break;
+            }
+        }
+        self.occupied.insert(start, end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn range(start: &str, end: &str) -> IpRange {
+        IpRange::new(ip(start), ip(end)).unwrap()
+    }
+
+    #[test]
+    fn ip_range_001() {
+        assert!(IpRange::new(ip("10.0.0.2"), ip("10.0.0.1")).is_none());
+        assert!(IpRange::new(ip("10.0.0.1"), ip("fd00::1")).is_none());
+        let net: IpNet = "10.0.0.0/24".parse().unwrap();
+        assert_eq!(IpRange::hosts(&net), range("10.0.0.1", "10.0.0.254"));
+        let net: IpNet = "fd00::/64".parse().unwrap();
+        assert_eq!(
+            IpRange::hosts(&net),
+            range("fd00::1", "fd00::ffff:ffff:ffff:ffff")
+        );
+        let net: IpNet = "fd00::/127".parse().unwrap();
+        assert_eq!(IpRange::hosts(&net), range("fd00::", "fd00::1"));
+        assert_eq!(range("10.0.0.1", "10.0.0.1").to_string(), "10.0.0.1");
+        assert_eq!(
+            range("10.0.0.1", "10.0.0.3").to_string(),
+            "10.0.0.1-10.0.0.3"
+        );
+        assert_eq!(range("10.0.0.1", "10.0.0.3").at_offset(4), ip("10.0.0.2"));
+        assert_eq!(
+            range("::", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff").at_offset(3),
+            ip("::3")
+        );
+        assert_eq!(next_addr(ip("255.255.255.255")), None);
+        assert_eq!(prev_addr(ip("::")), None);
+    }
+
+    #[test]
+    fn ip_range_to_nets_001() {
+        let nets =
+            |r: IpRange| -> Vec<String> { r.to_nets().iter().map(|n| n.to_string()).collect() };
+        assert_eq!(nets(range("10.0.0.4", "10.0.0.7")), vec!["10.0.0.4/30"]);
+        assert_eq!(
+            nets(range("10.0.0.3", "10.0.0.16")),
+            vec!["10.0.0.3/32", "10.0.0.4/30", "10.0.0.8/29", "10.0.0.16/32"]
+        );
+        assert_eq!(nets(range("10.0.0.1", "10.0.0.1")), vec!["10.0.0.1/32"]);
+        assert_eq!(nets(range("0.0.0.0", "255.255.255.255")), vec!["0.0.0.0/0"]);
+        assert_eq!(
+            nets(range("::", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")),
+            vec!["::/0"]
+        );
+        assert_eq!(
+            nets(range("fd00::1", "fd00::ffff:ffff:ffff:ffff")),
+            (0..64)
+                .map(|i| {
+                    let start = IpAddr::V6(Ipv6Addr::from((0xfd00u128 << 112) | (1u128 << i)));
+                    format!("{}/{}", start, 128 - i)
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn first_subnet_in_001() {
+        let uut = range("10.0.0.3", "10.0.0.40");
+        assert_eq!(
+            first_subnet_in(&uut, 29),
+            Some("10.0.0.8/29".parse().unwrap())
+        );
+        assert_eq!(
+            first_subnet_in(&uut, 28),
+            Some("10.0.0.16/28".parse().unwrap())
+        );
+        assert_eq!(first_subnet_in(&uut, 27), None);
+        assert_eq!(first_subnet_in(&uut, 33), None);
+        let uut = range("fd00::1", "fd00:0:0:1::");
+        assert_eq!(first_subnet_in(&uut, 64), None);
+        assert_eq!(
+            first_subnet_in(&uut, 65),
+            Some("fd00::8000:0:0:0/65".parse().unwrap())
+        );
+        assert_eq!(
+            first_subnet_in(&range("::", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"), 0),
+            Some("::/0".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn ip_range_fromstr_001() {
+        assert_eq!("10.0.0.1".parse().ok(), Some(range("10.0.0.1", "10.0.0.1")));
+        assert_eq!(
+            "10.0.0.2 - 10.0.0.20".parse().ok(),
+            Some(range("10.0.0.2", "10.0.0.20"))
+        );
+        assert_eq!(
+            "10.0.0.64/28".parse().ok(),
+            Some(range("10.0.0.64", "10.0.0.79"))
+        );
+        assert!("10.0.0.20-10.0.0.2".parse::<IpRange>().is_err());
+        assert!("10.0.0.2-fd00::1".parse::<IpRange>().is_err());
+        assert!("10.0.0".parse::<IpRange>().is_err());
+        let uut = parse_range_list("10.0.0.2-10.0.0.20,10.0.0.64/28,").unwrap();
+        assert_eq!(uut.len(), 2);
+        assert!(parse_range_list("").unwrap().is_empty());
+        assert!(parse_range_list("10.0.0.2,foo").is_err());
+    }
+
+    #[test]
+    fn ip_range_map_001() {
+        let mut uut = IpRangeMap::new();
+        uut.insert_range(range("10.0.0.0", "10.0.0.255"), "bridge");
+        uut.insert(ip("10.0.0.5"), "pot");
+        assert_eq!(uut.len(), 3);
+        assert_eq!(uut.get(&ip("10.0.0.4")), Some(&"bridge"));
+        assert_eq!(uut.get(&ip("10.0.0.5")), Some(&"pot"));
+        assert_eq!(uut.get(&ip("10.0.0.6")), Some(&"bridge"));
+        assert_eq!(uut.get(&ip("10.0.1.0")), None);
+        assert_eq!(
+            uut.get_range(&ip("10.0.0.6")).unwrap().0,
+            range("10.0.0.6", "10.0.0.255")
+        );
+        uut.insert(ip("10.0.0.0"), "network");
+        uut.insert(ip("10.0.0.255"), "broadcast");
+        let ranges: Vec<_> = uut.iter().map(|(r, v)| (r.to_string(), *v)).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                ("10.0.0.0".to_string(), "network"),
+                ("10.0.0.1-10.0.0.4".to_string(), "bridge"),
+                ("10.0.0.5".to_string(), "pot"),
+                ("10.0.0.6-10.0.0.254".to_string(), "bridge"),
+                ("10.0.0.255".to_string(), "broadcast"),
+            ]
+        );
+    }
+
+    #[test]
+    fn ip_range_map_002() {
+        let mut uut = IpRangeMap::new();
+        uut.insert(ip("10.0.0.3"), "pot");
+        uut.fill_range(range("10.0.0.0", "10.0.0.7"), "bridge");
+        assert_eq!(uut.get(&ip("10.0.0.3")), Some(&"pot"));
+        assert_eq!(uut.len(), 3);
+        assert_eq!(
+            uut.gaps(range("10.0.0.0", "10.0.0.15")),
+            vec![range("10.0.0.8", "10.0.0.15")]
+        );
+    }
+
+    #[test]
+    fn ip_range_map_003() {
+        let mut uut = IpRangeMap::new();
+        let pool = range("10.0.0.1", "10.0.0.254");
+        assert_eq!(uut.first_free(&pool), Some(ip("10.0.0.1")));
+        uut.insert(ip("10.0.0.1"), ());
+        uut.insert(ip("10.0.0.2"), ());
+        uut.insert(ip("10.0.0.4"), ());
+        assert_eq!(uut.first_free(&pool), Some(ip("10.0.0.3")));
+        uut.insert(ip("10.0.0.3"), ());
+        assert_eq!(uut.first_free(&pool), Some(ip("10.0.0.5")));
+        assert_eq!(uut.last_free(&pool), Some(ip("10.0.0.254")));
+        uut.insert_range(range("10.0.0.5", "10.0.0.254"), ());
+        assert_eq!(uut.first_free(&pool), None);
+        assert_eq!(uut.last_free(&pool), None);
+        assert!(uut.overlaps(&range("10.0.0.250", "10.0.1.0")));
+        assert!(!uut.overlaps(&range("10.0.0.255", "10.0.1.0")));
+    }
+
+    #[test]
+    fn ip_range_map_004() {
+        let mut uut = IpRangeMap::new();
+        let net: IpNet = "fd00::/48".parse().unwrap();
+        uut.insert(ip("fd00::"), ());
+        uut.fill_range(range("fd00::", "fd00::ffff:ffff:ffff:ffff"), ());
+        assert_eq!(
+            uut.first_free(&IpRange::hosts(&net)),
+            Some(ip("fd00:0:0:1::"))
+        );
+        assert_eq!(
+            uut.first_free_subnet(&net, 64),
+            Some("fd00:0:0:1::/64".parse().unwrap())
+        );
+        uut.insert(ip("fd00:0:0:1::1"), ());
+        assert_eq!(
+            uut.first_free_subnet(&net, 64),
+            Some("fd00:0:0:2::/64".parse().unwrap())
+        );
+        assert_eq!(uut.first_free_subnet(&net, 47), None);
+        let net: IpNet = "10.0.0.0/24".parse().unwrap();
+        uut.insert(ip("10.0.0.1"), ());
+        assert_eq!(
+            uut.first_free_subnet(&net, 30),
+            Some("10.0.0.4/30".parse().unwrap())
+        );
+        assert_eq!(uut.first_free_subnet(&net, 24), None);
+    }
+}
@@ -0,0 +1,201 @@
+use crate::error::PotError;
+use crate::util::{lock_exclusive, now, parse_records, read_optional};
+use crate::{PotSystemConfig, Result};
+use std::fs::File;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A temporary reservation of an address, until its pot.conf is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub ip: IpAddr,
+    pub owner: String,
+    /// Expiration time, in seconds since the epoch
+    pub expires: u64,
+}
+
+impl Lease {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires <= now
+    }
+}
+
+impl std::fmt::Display for Lease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.ip, self.owner, self.expires)
+    }
+}
+
+/// The lease database, stored in `fs_root`/potnet/leases
+///
+/// The database is protected by an advisory lock, held until the LeaseDb is dropped,
+/// so that the scan for a free address and its reservation are atomic
+#[derive(Debug)]
+pub struct LeaseDb {
+    path: PathBuf,
+    leases: Vec<Lease>,
+    _lock: File,
+}
+
+pub fn get_potnet_dir(conf: &PotSystemConfig) -> PathBuf {
+    Path::new(&conf.fs_root).join("potnet")
+}
+
+// the lines are `<address> <owner> <expiration time>`
+fn parse_leases(path: &Path, s: &str) -> Result<Vec<Lease>> {
+    parse_records(
+        path,
+        s,
+        |fields| match fields {
+            [ip, owner, expires] => Some(Lease {
+                ip: ip.parse().ok()?,
+                owner: owner.to_string(),
+                expires: expires.parse().ok()?,
+            }),
+            _ => None,
+        },
+        PotError::LeaseDbError,
+    )
+}
+
+/// The leases not expired yet, read without taking the lock
+///
+/// A missing lease database is equivalent to an empty one
+pub fn get_live_leases(conf: &PotSystemConfig) -> Result<Vec<Lease>> {
+    let path = get_potnet_dir(conf).join("leases");
+    let s = match read_optional(&path)? {
+        Some(s) => s,
+        None => return Ok(Vec::new()),
+    };
+    let now = now();
+    Ok(parse_leases(&path, &s)?
+        .into_iter()
+        .filter(|l| !l.is_expired(now))
+        .collect())
+}
+
+impl LeaseDb {
+    /// Lock and read the lease database of the pot installation
+    pub fn open(conf: &PotSystemConfig) -> Result<Self> {
+        let dir = get_potnet_dir(conf);
+        std::fs::create_dir_all(&dir)?;
+        LeaseDb::open_file(&dir.join("leases"))
+    }
+
+    /// Lock and read the lease database stored in `path`, dropping the expired leases
+    pub fn open_file(path: &Path) -> Result<Self> {
+        let lock = lock_exclusive(&path.with_extension("lock"))?;
+        let leases = match read_optional(path)? {
+            Some(s) => parse_leases(path, &s)?,
+            None => Vec::new(),
+        };
+        let now = now();
+        Ok(LeaseDb {
+            path: path.to_path_buf(),
+            leases: leases.into_iter().filter(|l| !l.is_expired(now)).collect(),
+            _lock: lock,
+        })
+    }
+
+    pub fn leases(&self) -> &[Lease] {
+        &self.leases
+    }
+
+    pub fn get(&self, ip: IpAddr) -> Option<&Lease> {
+        self.leases.iter().find(|l| l.ip == ip)
+    }
+
+    /// Reserve `ip` for `owner`, or renew the lease if `owner` already has it
+    pub fn reserve(&mut self, ip: IpAddr, owner: &str, ttl: Duration) -> Result<()> {
+        if owner.is_empty() || owner.contains(char::is_whitespace) {
+            return Err(PotError::ConfValueError(
+                "lease owner".to_string(),
+                owner.to_string(),
+            ));
+        }
+        if let Some(lease) = self.get(ip) {
+            if lease.owner != owner {
+                return Err(PotError::LeaseConflict(ip, lease.owner.clone()));
+            }
+        }
+        self.leases.retain(|l| l.ip != ip);
+        self.leases.push(Lease {
+            ip,
+            owner: owner.to_string(),
+            expires: now() + ttl.as_secs(),
+        });
+        Ok(())
+    }
+
+    /// Release the lease on `ip`, if any
+    pub fn release(&mut self, ip: IpAddr) -> Option<Lease> {
+        let index = self.leases.iter().position(|l| l.ip == ip)?;
+        Some(self.leases.remove(index))
+    }
+
+    /// Release all the leases of `owner`
+    pub fn release_owner(&mut self, owner: &str) -> Vec<Lease> {
+        let (released, kept) = self.leases.drain(..).partition(|l| l.owner == owner);
+        self.leases = kept;
+        released
+    }
+
+    /// Write the database; the lock is kept until the LeaseDb is dropped
+    pub fn save(&self) -> Result<()> {
+        let mut content = String::new();
+        for lease in &self.leases {
+            content.push_str(&lease.to_string());
+            content.push('\n');
+        }
+        crate::util::write_atomic(&self.path, content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_leases_001() {
+        let path = Path::new("leases");
+        let uut = parse_leases(path, "# leases\n\n10.192.0.3 foo 1600000000\n").unwrap();
+        assert_eq!(
+            uut,
+            vec![Lease {
+                ip: "10.192.0.3".parse().unwrap(),
+                owner: "foo".to_string(),
+                expires: 1_600_000_000
+            }]
+        );
+        assert!(parse_leases(path, "10.192.0.3 foo").is_err());
+        assert!(parse_leases(path, "10.192.0.x foo 1600000000").is_err());
+    }
+
+    #[test]
+    fn lease_db_001() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("leases");
+        let ip = "10.192.0.3".parse().unwrap();
+        {
+            let mut uut = LeaseDb::open_file(&path).unwrap();
+            assert!(uut.leases().is_empty());
+            uut.reserve(ip, "foo", Duration::from_secs(600)).unwrap();
+            assert!(uut.reserve(ip, "bar", Duration::from_secs(600)).is_err());
+            uut.reserve(ip, "foo", Duration::from_secs(900)).unwrap();
+            assert!(uut
+                .reserve(ip, "foo bar", Duration::from_secs(900))
+                .is_err());
+            uut.reserve("10.192.0.4".parse().unwrap(), "bar", Duration::from_secs(0))
+                .unwrap();
+            assert_eq!(uut.leases().len(), 2);
+            uut.save().unwrap();
+        }
+        let mut uut = LeaseDb::open_file(&path).unwrap();
+        assert_eq!(uut.leases().len(), 1);
+        assert_eq!(uut.get(ip).unwrap().owner, "foo".to_string());
+        assert_eq!(uut.release_owner("foo").len(), 1);
+        assert!(uut.release(ip).is_none());
+        assert!(uut.leases().is_empty());
+    }
+}
@@ -1,19 +1,32 @@
+pub mod allocation;
 pub mod bridge;
+pub mod confdoc;
+pub mod diagnostic;
 pub mod error;
+pub mod ipam;
+pub mod iprange;
+pub mod lease;
+pub mod potconf;
+pub mod quarantine;
+pub mod reservation;
+pub mod runner;
 mod system;
 pub(crate) mod util;
 
+use allocation::Strategy;
+use diagnostic::Diagnostic;
 use ipnet::IpNet;
+use iprange::IpRange;
+use runner::{CommandRunner, SystemRunner};
 use std::convert::TryFrom;
 use std::default::Default;
-use std::fs::File;
-use std::io::prelude::*;
 use std::net::IpAddr;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use walkdir::WalkDir;
 
+pub use system::ConfigSource;
+
 pub type Result<T> = ::std::result::Result<T, error::PotError>;
 
 #[derive(Debug, Clone)]
@@ -26,12 +39,102 @@ pub struct PotSystemConfig {
     pub ext_if: String,
     pub dns_name: String,
     pub dns_ip: IpAddr,
+    /// The optional IPv6 pool of a dual-stack system (POT_NETWORK6)
+    pub network6: Option<IpNet>,
+    pub gateway6: Option<IpAddr>,
+    pub dns_ip6: Option<IpAddr>,
+    /// The addresses never handed out by potnet (POT_NETWORK_RESERVED)
+    pub reserved: Vec<IpRange>,
+    /// How potnet chooses a free address (POT_IP_ALLOCATION)
+    pub ip_allocation: Strategy,
+    /// How long a released address is not handed out, in seconds, 0 to disable
+    /// the quarantine (POT_IP_QUARANTINE)
+    pub ip_quarantine: u64,
+    /// Where the configuration has been read from, None if not read from files
+    pub source: Option<ConfigSource>,
 }
 
 impl PotSystemConfig {
+    /// Find the configuration via POT_CONF, POT_PREFIX or the pot executable in PATH
     pub fn from_system() -> Result<Self> {
-        let psc = system::PartialSystemConf::new();
-        PotSystemConfig::try_from(psc)
+        PotSystemConfig::from_system_with_runner(&SystemRunner)
+    }
+
+    pub fn from_system_with_runner(runner: &dyn CommandRunner) -> Result<Self> {
+        let source = system::locate_conf(
+            runner,
+            std::env::var_os("POT_CONF").map(PathBuf::from),
+            std::env::var_os("POT_PREFIX").map(PathBuf::from),
+        )?;
+        PotSystemConfig::from_source(source)
+    }
+
+    /// Read the configuration files installed under `prefix`/etc/pot
+    pub fn from_prefix<P: AsRef<Path>>(prefix: P) -> Result<Self> {
+        PotSystemConfig::from_source(ConfigSource::Prefix(prefix.as_ref().to_path_buf()))
+    }
+
+    /// Read the user configuration file `user` and the pot.default.conf in the same directory
+    pub fn from_conf<P: AsRef<Path>>(user: P) -> Result<Self> {
+        PotSystemConfig::from_source(ConfigSource::Conf(user.as_ref().to_path_buf()))
+    }
+
+    /// Read the default configuration file, overridden by the user one, if it exists
+    pub fn from_files<P: AsRef<Path>, Q: AsRef<Path>>(default: P, user: Q) -> Result<Self> {
+        PotSystemConfig::from_source(ConfigSource::Files {
+            default: default.as_ref().to_path_buf(),
+            user: user.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn from_source(source: ConfigSource) -> Result<Self> {
+        let (default, user) = source.conf_files();
+        let psc = system::PartialSystemConf::from_files(&default, &user)?;
+        let mut conf = PotSystemConfig::try_from(psc)?;
+        conf.source = Some(source);
+        Ok(conf)
+    }
+}
+
+/// An address pool: a network, its gateway and, if any, the address of the DNS pot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkPool {
+    pub network: IpNet,
+    pub gateway: IpAddr,
+    pub dns_ip: Option<IpAddr>,
+}
+
+impl PotSystemConfig {
+    /// The pool configured via POT_NETWORK, IPv4 or IPv6
+    pub fn main_pool(&self) -> NetworkPool {
+        NetworkPool {
+            network: self.network,
+            gateway: self.gateway,
+            dns_ip: Some(self.dns_ip),
+        }
+    }
+
+    /// The IPv6 pool configured via POT_NETWORK6, in a dual-stack system
+    pub fn pool6(&self) -> Option<NetworkPool> {
+        Some(NetworkPool {
+            network: self.network6?,
+            gateway: self.gateway6?,
+            dns_ip: self.dns_ip6,
+        })
+    }
+
+    /// All the configured pools, the main one first
+    pub fn pools(&self) -> Vec<NetworkPool> {
+        std::iter::once(self.main_pool())
+            .chain(self.pool6())
+            .collect()
+    }
+
+    /// The pool providing addresses of the given family, if any
+    pub fn pool_for(&self, ipv6: bool) -> Option<NetworkPool> {
+        self.pools()
+            .into_iter()
+            .find(|pool| matches!(pool.network, IpNet::V6(_)) == ipv6)
     }
 }
 
@@ -39,6 +142,19 @@ impl TryFrom<system::PartialSystemConf> for PotSystemConfig {
     type Error = error::PotError;
 
     fn try_from(psc: system::PartialSystemConf) -> std::result::Result<Self, Self::Error> {
+        use error::PotError::MissingSystemConfValue;
+        match (&psc.network6, &psc.gateway6, &psc.dns_ip6) {
+            (Some(_), None, _) => {
+                return Err(MissingSystemConfValue("POT_GATEWAY6", "POT_NETWORK6"))
+            }
+            (None, Some(_), _) => {
+                return Err(MissingSystemConfValue("POT_NETWORK6", "POT_GATEWAY6"))
+            }
+            (None, _, Some(_)) => {
+                return Err(MissingSystemConfValue("POT_NETWORK6", "POT_DNS_IP6"))
+            }
+            _ => {}
+        }
         if psc.is_valid() {
             Ok(PotSystemConfig {
                 zfs_root: psc.zfs_root.unwrap(),
@@ -49,6 +165,13 @@ impl TryFrom<system::PartialSystemConf> for PotSystemConfig {
                 ext_if: psc.ext_if.unwrap(),
                 dns_name: psc.dns_name.unwrap(),
                 dns_ip: psc.dns_ip.unwrap(),
+                network6: psc.network6,
+                gateway6: psc.gateway6,
+                dns_ip6: psc.dns_ip6,
+                reserved: psc.network_reserved.unwrap_or_default(),
+                ip_allocation: psc.ip_allocation.unwrap_or_default(),
+                ip_quarantine: psc.ip_quarantine.unwrap_or_default(),
+                source: None,
             })
         } else {
             Err(error::PotError::IncompleteSystemConf)
@@ -56,7 +179,7 @@ impl TryFrom<system::PartialSystemConf> for PotSystemConfig {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NetType {
     Inherit,
     Alias,
@@ -64,11 +187,44 @@ pub enum NetType {
     PrivateBridge,
 }
 
+impl FromStr for NetType {
+    type Err = error::PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "inherit" => Ok(NetType::Inherit),
+            "alias" => Ok(NetType::Alias),
+            "public-bridge" => Ok(NetType::PublicBridge),
+            "private-bridge" => Ok(NetType::PrivateBridge),
+            _ => Err(error::PotError::ConfValueError(
+                "network_type".to_string(),
+                s.to_string(),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for NetType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            NetType::Inherit => "inherit",
+            NetType::Alias => "alias",
+            NetType::PublicBridge => "public-bridge",
+            NetType::PrivateBridge => "private-bridge",
+        };
+        write!(f, "{}", s)
+    }
+}
+
 #[derive(Debug)]
 pub struct PotConf {
     pub name: String,
+    /// The first address of the pot
     pub ip_addr: Option<IpAddr>,
+    /// All the addresses of the pot, e.g. an IPv4 and an IPv6 one in a dual-stack system
+    pub ip_addrs: Vec<IpAddr>,
     pub network_type: NetType,
+    /// The private bridge of the pot, from the bridge key
+    pub bridge: Option<String>,
 }
 
 #[derive(Debug, Default)]
@@ -84,7 +240,9 @@ impl Default for PotConf {
         PotConf {
             name: String::default(),
             ip_addr: None,
+            ip_addrs: Vec::new(),
             network_type: NetType::Inherit,
+            bridge: None,
         }
     }
 }
@@ -114,25 +272,18 @@ pub fn get_pot_list(conf: &PotSystemConfig) -> Vec<String> {
     result
 }
 
-fn is_pot_running(pot_name: &str) -> Result<bool> {
-    let status = Command::new("/usr/sbin/jls")
-        .arg("-j")
-        .arg(pot_name)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .status();
-    if let Ok(status) = status {
-        Ok(status.success())
+fn is_pot_running(pot_name: &str, runner: &dyn CommandRunner) -> Result<bool> {
+    if let Ok(output) = runner.run("/usr/sbin/jls", &["-j", pot_name]) {
+        Ok(output.success())
     } else {
         Err(error::PotError::JlsError)
     }
 }
 
-pub fn get_running_pot_list(conf: &PotSystemConfig) -> Vec<String> {
+pub fn get_running_pot_list(conf: &PotSystemConfig, runner: &dyn CommandRunner) -> Vec<String> {
     let mut result = Vec::new();
     for pot in get_pot_list(conf) {
-        if let Ok(status) = is_pot_running(&pot) {
+        if let Ok(status) = is_pot_running(&pot, runner) {
             if status {
                 result.push(pot);
             }
@@ -141,92 +292,386 @@ pub fn get_running_pot_list(conf: &PotSystemConfig) -> Vec<String> {
     result
 }
 
-pub fn get_pot_conf_list(conf: PotSystemConfig) -> Vec<PotConf> {
-    let mut v: Vec<PotConf> = Vec::new();
+/// A pot configuration, with the problems found while reading it
+#[derive(Debug)]
+pub struct PotConfReport {
+    pub name: String,
+    /// None if the configuration cannot be understood
+    pub conf: Option<PotConf>,
+    pub diagnostics: Vec<Diagnostic>,
+}
 
-    let fsroot = conf.fs_root.clone();
-    let pdir = fsroot + "/jails/";
-    for mut dir_path in get_pot_path_list(&conf) {
-        let mut pot_conf = PotConf {
-            name: dir_path
-                .clone()
-                .strip_prefix(&pdir)
-                .ok()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string(),
-            ..Default::default()
+impl PotConfReport {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.severity == diagnostic::Severity::Error)
+    }
+}
+
+/// Read all the pot configurations, reporting the problems found for each pot
+pub fn get_pot_conf_reports(conf: &PotSystemConfig) -> Vec<PotConfReport> {
+    let mut result = Vec::new();
+    for dir_path in get_pot_path_list(conf) {
+        let file_name = dir_path.file_name().unwrap_or_default();
+        let conf_path = dir_path.join("conf").join("pot.conf");
+        let name = match file_name.to_str() {
+            Some(name) => name.to_string(),
+            None => {
+                result.push(PotConfReport {
+                    name: file_name.to_string_lossy().to_string(),
+                    conf: None,
+                    diagnostics: vec![Diagnostic::error(&conf_path, "pot name is not valid UTF-8")],
+                });
+                continue;
+            }
         };
-        dir_path.push("conf");
-        dir_path.push("pot.conf");
-        let mut conf_file = match File::open(dir_path.as_path()) {
-            Ok(x) => x,
-            Err(_) => continue,
+        let report = match std::fs::read_to_string(&conf_path) {
+            Ok(conf_str) => analyze_pot_conf(&name, &conf_path, &conf_str),
+            Err(e) => PotConfReport {
+                name,
+                conf: None,
+                diagnostics: vec![Diagnostic::error(
+                    &conf_path,
+                    &format!("cannot read the file: {}", e),
+                )],
+            },
         };
-        let mut conf_str = String::new();
-        match conf_file.read_to_string(&mut conf_str) {
-            Ok(_) => (),
-            Err(_) => continue,
-        }
-        let mut temp_pot_conf = PotConfVerbatim::default();
-        for s in conf_str.lines() {
-            if s.starts_with("ip4=") {
-                temp_pot_conf.ip4 = Some(s.split('=').nth(1).unwrap().to_string());
-            }
-            if s.starts_with("ip=") {
-                temp_pot_conf.ip = Some(s.split('=').nth(1).unwrap().to_string());
-            }
-            if s.starts_with("vnet=") {
-                temp_pot_conf.vnet = Some(s.split('=').nth(1).unwrap().to_string());
-            }
-            if s.starts_with("network_type=") {
-                temp_pot_conf.network_type = Some(s.split('=').nth(1).unwrap().to_string());
-            }
+        result.push(report);
+    }
+    result
+}
+
+// the ip value can contain multiple addresses, separated by spaces; None if one is not valid
+fn parse_ip_list(s: &str) -> Option<Vec<IpAddr>> {
+    let result: Vec<IpAddr> = s
+        .split_whitespace()
+        .map(IpAddr::from_str)
+        .collect::<std::result::Result<_, _>>()
+        .ok()?;
+    if result.is_empty() {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+fn analyze_pot_conf(name: &str, conf_path: &Path, conf_str: &str) -> PotConfReport {
+    let parsed = potconf::PotConfig::parse(conf_str);
+    let mut diagnostics = Vec::new();
+    let mut repeated: Vec<(usize, &str)> = ["ip4", "ip", "vnet", "network_type"]
+        .iter()
+        .flat_map(|key| {
+            parsed
+                .lines
+                .get(*key)
+                .into_iter()
+                .flat_map(|lines| lines.iter().skip(1))
+                .map(move |line| (*line, *key))
+        })
+        .collect();
+    repeated.sort_unstable();
+    for (line, key) in repeated {
+        diagnostics.push(
+            Diagnostic::warning(conf_path, "defined multiple times, the last one is used")
+                .at(Some(line), key),
+        );
+    }
+    let mut invalid_network_type = false;
+    for (key, line, _) in &parsed.invalid {
+        if key == "network_type" {
+            invalid_network_type = true;
+        } else {
+            diagnostics.push(
+                Diagnostic::warning(conf_path, "invalid value, ignored").at(Some(*line), key),
+            );
         }
-        if let Some(network_type) = temp_pot_conf.network_type {
-            pot_conf.network_type = match network_type.as_str() {
-                "inherit" => NetType::Inherit,
-                "alias" => NetType::Alias,
-                "public-bridge" => NetType::PublicBridge,
-                "private-bridge" => NetType::PrivateBridge,
-                _ => continue,
-            };
-            if pot_conf.network_type == NetType::Alias {
-                continue;
-            }
-            if pot_conf.network_type == NetType::PublicBridge
-                || pot_conf.network_type == NetType::PrivateBridge
-            {
-                if let Some(ip_addr) = temp_pot_conf.ip {
-                    pot_conf.ip_addr = Some(IpAddr::from_str(&ip_addr).ok().unwrap())
-                } else {
-                    // Error !
-                    continue;
+    }
+    let line = |key: &str| parsed.lines.get(key).and_then(|l| l.last()).copied();
+    let mut conf_error = |key: &str, reason: &str| {
+        diagnostics.push(Diagnostic::error(conf_path, reason).at(line(key), key));
+        None
+    };
+    let config = &parsed.config;
+    let mut pot_conf = PotConf {
+        name: name.to_string(),
+        bridge: config.bridge.clone(),
+        ..Default::default()
+    };
+    let pot_conf = if invalid_network_type {
+        conf_error("network_type", "unknown network type")
+    } else if let Some(network_type) = config.network_type {
+        pot_conf.network_type = network_type;
+        if network_type == NetType::PublicBridge || network_type == NetType::PrivateBridge {
+            match config.ip.as_deref().map(parse_ip_list) {
+                Some(Some(ip_addrs)) => {
+                    pot_conf.ip_addr = ip_addrs.first().copied();
+                    pot_conf.ip_addrs = ip_addrs;
+                    Some(pot_conf)
                 }
+                Some(None) => conf_error("ip", "invalid IP address"),
+                None => conf_error("network_type", "bridge network type without ip"),
             }
-        } else if let Some(ip4) = temp_pot_conf.ip4 {
-            // Old pot version - compatibility mode
-            if &ip4 == "inherit" {
-                pot_conf.network_type = NetType::Inherit;
-            } else {
-                pot_conf.ip_addr = Some(IpAddr::from_str(&ip4).ok().unwrap());
-                if let Some(vnet) = temp_pot_conf.vnet {
-                    if &vnet == "true" {
-                        pot_conf.network_type = NetType::PublicBridge;
+        } else {
+            Some(pot_conf)
+        }
+    } else if let Some(ip4) = &config.ip4 {
+        // Old pot version - compatibility mode
+        if ip4 == "inherit" {
+            pot_conf.network_type = NetType::Inherit;
+            Some(pot_conf)
+        } else {
+            match (IpAddr::from_str(ip4), config.vnet) {
+                (Err(_), _) => conf_error("ip4", "invalid IP address"),
+                (Ok(_), None) => conf_error("ip4", "ip4 without vnet"),
+                (Ok(ip_addr), Some(vnet)) => {
+                    pot_conf.ip_addr = Some(ip_addr);
+                    pot_conf.ip_addrs = vec![ip_addr];
+                    pot_conf.network_type = if vnet {
+                        NetType::PublicBridge
                     } else {
-                        pot_conf.network_type = NetType::Alias;
-                    }
-                } else {
-                    // Error
-                    continue;
+                        NetType::Alias
+                    };
+                    Some(pot_conf)
                 }
             }
-        } else {
-            // Error !
-            continue;
         }
-        v.push(pot_conf);
+    } else {
+        diagnostics.push(Diagnostic::error(
+            conf_path,
+            "no network configuration (network_type or ip4)",
+        ));
+        None
+    };
+    PotConfReport {
+        name: name.to_string(),
+        conf: pot_conf,
+        diagnostics,
+    }
+}
+
+/// The configuration of all the pots that can be understood, alias excluded
+///
+/// Pots with problems are skipped: use get_pot_conf_reports() to know why
+pub fn get_pot_conf_list(conf: PotSystemConfig) -> Vec<PotConf> {
+    get_pot_conf_reports(&conf)
+        .into_iter()
+        .filter_map(|report| report.conf)
+        .filter(|pot_conf| pot_conf.network_type != NetType::Alias)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runner::ScriptedRunner;
+
+    pub(crate) fn test_conf(fs_root: &std::path::Path) -> PotSystemConfig {
+        PotSystemConfig {
+            zfs_root: "zroot/pot".to_string(),
+            fs_root: fs_root.to_str().unwrap().to_string(),
+            network: "10.192.0.0/10".parse().unwrap(),
+            netmask: "255.192.0.0".parse().unwrap(),
+            gateway: "10.192.0.1".parse().unwrap(),
+            ext_if: "em0".to_string(),
+            dns_name: "dns".to_string(),
+            dns_ip: "10.192.0.2".parse().unwrap(),
+            network6: None,
+            gateway6: None,
+            dns_ip6: None,
+            reserved: Vec::new(),
+            ip_allocation: Strategy::Lowest,
+            ip_quarantine: 0,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn get_running_pot_list_001() {
+        let fs_root = tempfile::tempdir().unwrap();
+        for pot in &["foo", "bar", "baz"] {
+            std::fs::create_dir_all(fs_root.path().join("jails").join(pot)).unwrap();
+        }
+        let conf = test_conf(fs_root.path());
+        let runner = ScriptedRunner::new()
+            .with_output("/usr/sbin/jls", &["-j", "foo"], 0, "")
+            .with_output("/usr/sbin/jls", &["-j", "bar"], 1, "");
+        let mut uut = get_running_pot_list(&conf, &runner);
+        uut.sort();
+        assert_eq!(uut, vec!["foo".to_string()]);
+    }
+
+    #[test]
+    fn analyze_pot_conf_001() {
+        let path = Path::new("pot.conf");
+        let uut = analyze_pot_conf("foo", path, "network_type=public-bridge\nip=10.192.0.3\n");
+        assert!(uut.diagnostics.is_empty());
+        let pot_conf = uut.conf.unwrap();
+        assert_eq!(pot_conf.name, "foo".to_string());
+        assert_eq!(pot_conf.network_type, NetType::PublicBridge);
+        assert_eq!(pot_conf.ip_addr, Some("10.192.0.3".parse().unwrap()));
+        let uut = analyze_pot_conf("foo", path, "ip4=inherit");
+        assert!(uut.diagnostics.is_empty());
+        assert_eq!(uut.conf.unwrap().network_type, NetType::Inherit);
+        let uut = analyze_pot_conf("foo", path, "ip4=10.192.0.3\nvnet=true");
+        assert!(uut.diagnostics.is_empty());
+        assert_eq!(uut.conf.unwrap().network_type, NetType::PublicBridge);
+    }
+
+    #[test]
+    fn analyze_pot_conf_002() {
+        let path = Path::new("pot.conf");
+        let uut = analyze_pot_conf(
+            "foo",
+            path,
+            "pot.level=0\nnetwork_type=private-bridge\nip=10.192.0.300\n",
+        );
+        assert!(uut.conf.is_none());
+        assert!(uut.has_errors());
+        assert_eq!(uut.diagnostics.len(), 1);
+        assert_eq!(uut.diagnostics[0].line, Some(3));
+        assert_eq!(uut.diagnostics[0].key, Some("ip".to_string()));
+        let uut = analyze_pot_conf("foo", path, "network_type=public-bridge");
+        assert!(uut.conf.is_none());
+        assert_eq!(uut.diagnostics[0].line, Some(1));
+        let uut = analyze_pot_conf("foo", path, "network_type=bridge\nip=10.192.0.3");
+        assert!(uut.conf.is_none());
+        assert_eq!(uut.diagnostics[0].key, Some("network_type".to_string()));
+        let uut = analyze_pot_conf("foo", path, "ip4=10.192.0.3");
+        assert!(uut.conf.is_none());
+        let uut = analyze_pot_conf("foo", path, "pot.level=0");
+        assert!(uut.conf.is_none());
+        assert!(uut.has_errors());
+    }
+
+    #[test]
+    fn analyze_pot_conf_003() {
+        let path = Path::new("pot.conf");
+        let uut = analyze_pot_conf(
+            "foo",
+            path,
+            "network_type=public-bridge\nip=10.192.0.3\nip=10.192.0.4\n",
+        );
+        assert!(!uut.has_errors());
+        assert_eq!(uut.diagnostics.len(), 1);
+        assert_eq!(uut.diagnostics[0].line, Some(3));
+        assert_eq!(
+            uut.conf.unwrap().ip_addr,
+            Some("10.192.0.4".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn analyze_pot_conf_005() {
+        let path = Path::new("pot.conf");
+        let uut = analyze_pot_conf(
+            "foo",
+            path,
+            "network_type=\"public-bridge\"\nip=\"10.192.0.3\" # static address\npot.level=x\n",
+        );
+        assert!(!uut.has_errors());
+        assert_eq!(uut.diagnostics.len(), 1);
+        assert_eq!(uut.diagnostics[0].key, Some("pot.level".to_string()));
+        assert_eq!(
+            uut.conf.unwrap().ip_addr,
+            Some("10.192.0.3".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn analyze_pot_conf_004() {
+        let path = Path::new("pot.conf");
+        let uut = analyze_pot_conf(
+            "foo",
+            path,
+            "network_type=public-bridge\nip=10.192.0.3 fdf1:186e:49e6:76d8::3\n",
+        );
+        assert!(uut.diagnostics.is_empty());
+        let pot_conf = uut.conf.unwrap();
+        assert_eq!(pot_conf.ip_addr, Some("10.192.0.3".parse().unwrap()));
+        assert_eq!(
+            pot_conf.ip_addrs,
+            vec![
+                "10.192.0.3".parse::<IpAddr>().unwrap(),
+                "fdf1:186e:49e6:76d8::3".parse().unwrap()
+            ]
+        );
+        let uut = analyze_pot_conf("foo", path, "network_type=public-bridge\nip=10.192.0.3 x");
+        assert!(uut.has_errors());
+    }
+
+    #[test]
+    fn pot_system_config_pools_001() {
+        let mut uut = test_conf(Path::new("/opt/pot"));
+        assert_eq!(uut.pools().len(), 1);
+        assert_eq!(uut.pool_for(false), Some(uut.main_pool()));
+        assert_eq!(uut.pool_for(true), None);
+        uut.network6 = Some("fdf1:186e:49e6:76d8::/64".parse().unwrap());
+        uut.gateway6 = Some("fdf1:186e:49e6:76d8::1".parse().unwrap());
+        assert_eq!(uut.pools().len(), 2);
+        let pool6 = uut.pool_for(true).unwrap();
+        assert_eq!(pool6.gateway, uut.gateway6.unwrap());
+        assert_eq!(pool6.dns_ip, None);
+    }
+
+    #[test]
+    fn get_pot_conf_reports_001() {
+        let fs_root = tempfile::tempdir().unwrap();
+        let conf = test_conf(fs_root.path());
+        for (pot, pot_conf) in &[
+            ("foo", Some("network_type=public-bridge\nip=10.192.0.3\n")),
+            ("bar", Some("network_type=public-bridge\nip=10.192.0.x\n")),
+            ("baz", None),
+        ] {
+            let conf_dir = fs_root.path().join("jails").join(pot).join("conf");
+            std::fs::create_dir_all(&conf_dir).unwrap();
+            if let Some(pot_conf) = pot_conf {
+                std::fs::write(conf_dir.join("pot.conf"), pot_conf).unwrap();
+            }
+        }
+        let mut uut = get_pot_conf_reports(&conf);
+        uut.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(uut.len(), 3);
+        assert!(uut[0].has_errors());
+        assert!(uut[1].has_errors());
+        assert!(!uut[2].has_errors());
+        let uut = get_pot_conf_list(conf);
+        assert_eq!(uut.len(), 1);
+        assert_eq!(uut[0].name, "foo".to_string());
+    }
+
+    #[test]
+    fn pot_system_config_from_prefix_001() {
+        let prefix = tempfile::tempdir().unwrap();
+        let conf_dir = prefix.path().join("etc").join("pot");
+        std::fs::create_dir_all(&conf_dir).unwrap();
+        std::fs::write(
+            conf_dir.join("pot.default.conf"),
+            "POT_ZFS_ROOT=zroot/pot\nPOT_FS_ROOT=/opt/pot\nPOT_EXTIF=em0\n\
+            POT_NETWORK=10.192.0.0/10\nPOT_NETMASK=255.192.0.0\nPOT_GATEWAY=10.192.0.1\n\
+            POT_DNS_IP=10.192.0.2\nPOT_DNS_NAME=dns\n",
+        )
+        .unwrap();
+        let uut = PotSystemConfig::from_prefix(prefix.path()).unwrap();
+        assert_eq!(uut.dns_name, "dns".to_string());
+        assert_eq!(
+            uut.source,
+            Some(ConfigSource::Prefix(prefix.path().to_path_buf()))
+        );
+        std::fs::write(conf_dir.join("pot.conf"), "POT_DNS_NAME=foo_dns\n").unwrap();
+        let uut = PotSystemConfig::from_conf(conf_dir.join("pot.conf")).unwrap();
+        assert_eq!(uut.dns_name, "foo_dns".to_string());
+        let uut = PotSystemConfig::from_files(
+            conf_dir.join("pot.default.conf"),
+            conf_dir.join("missing.conf"),
+        )
+        .unwrap();
+        assert_eq!(uut.dns_name, "dns".to_string());
+    }
+
+    #[test]
+    fn pot_system_config_from_prefix_002() {
+        let prefix = tempfile::tempdir().unwrap();
+        assert!(PotSystemConfig::from_prefix(prefix.path()).is_err());
     }
-    v
 }
@@ -0,0 +1,253 @@
+use crate::error::PotError;
+use crate::{NetType, PotSystemConfig, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The IP stack of a pot (pot.stack)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpStack {
+    Ipv4,
+    Ipv6,
+    Dual,
+}
+
+impl FromStr for IpStack {
+    type Err = PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ipv4" => Ok(IpStack::Ipv4),
+            "ipv6" => Ok(IpStack::Ipv6),
+            "dual" => Ok(IpStack::Dual),
+            _ => Err(PotError::ConfValueError(
+                "pot.stack".to_string(),
+                s.to_string(),
+            )),
+        }
+    }
+}
+
+/// The content of a pot.conf file
+///
+/// Keys that can appear multiple times (pot.depend, pot.env) are collected in order,
+/// keys not recognized are kept in `extras`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PotConfig {
+    /// host.hostname
+    pub hostname: Option<String>,
+    /// pot.level
+    pub level: Option<u32>,
+    /// pot.potbase
+    pub potbase: Option<String>,
+    /// pot.depend
+    pub depend: Vec<String>,
+    /// pot.export.ports
+    pub export_ports: Vec<String>,
+    /// pot.attr.*, indexed by the attribute name
+    pub attributes: BTreeMap<String, String>,
+    /// pot.stack
+    pub stack: Option<IpStack>,
+    /// network_type
+    pub network_type: Option<NetType>,
+    /// ip, verbatim, it can contain multiple addresses
+    pub ip: Option<String>,
+    /// ip4, used by older pot versions
+    pub ip4: Option<String>,
+    /// vnet, used by older pot versions
+    pub vnet: Option<bool>,
+    /// bridge
+    pub bridge: Option<String>,
+    /// pot.cmd
+    pub cmd: Option<String>,
+    /// pot.env
+    pub env: Vec<String>,
+    /// Every other key
+    pub extras: BTreeMap<String, String>,
+}
+
+impl PotConfig {
+    /// The value of a pot attribute (pot.attr.`name`)
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+}
+
+/// A pot.conf file, with the lines of the assignments and the values not valid
+#[derive(Debug)]
+pub(crate) struct ParsedPotConfig {
+    /// The configuration, where the keys with a value not valid are unset
+    pub(crate) config: PotConfig,
+    /// The lines, starting from 1, where each key is assigned
+    pub(crate) lines: BTreeMap<String, Vec<usize>>,
+    /// The keys with a value not valid, with their line and the reason
+    pub(crate) invalid: Vec<(String, usize, PotError)>,
+}
+
+impl PotConfig {
+    /// Parse a pot.conf file without stopping at the values not valid
+    pub(crate) fn parse(s: &str) -> ParsedPotConfig {
+        use crate::util::parse_assignment_words;
+        let mut result = ParsedPotConfig {
+            config: PotConfig::default(),
+            lines: BTreeMap::new(),
+            invalid: Vec::new(),
+        };
+        for (i, (key, value)) in s
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| Some((i + 1, parse_assignment_words(line)?)))
+        {
+            result.lines.entry(key.clone()).or_default().push(i);
+            if let Err(e) = result.config.set(&key, value) {
+                result.invalid.push((key, i, e));
+            }
+        }
+        result
+    }
+
+    // as in the shell, the last assignment wins, except for the keys that can be repeated
+    fn set(&mut self, key: &str, value: String) -> Result<()> {
+        match key {
+            "host.hostname" => self.hostname = Some(value),
+            "pot.level" => {
+                self.level = None;
+                self.level = Some(
+                    value
+                        .parse()
+                        .map_err(|_| PotError::ConfValueError(key.to_string(), value))?,
+                )
+            }
+            "pot.potbase" => self.potbase = Some(value),
+            "pot.depend" => self.depend.push(value),
+            "pot.export.ports" => {
+                self.export_ports = value.split_whitespace().map(str::to_string).collect()
+            }
+            "pot.stack" => {
+                self.stack = None;
+                self.stack = Some(value.parse()?)
+            }
+            "network_type" => {
+                self.network_type = None;
+                self.network_type = Some(value.parse()?)
+            }
+            "ip" => self.ip = Some(value),
+            "ip4" => self.ip4 = Some(value),
+            "vnet" => self.vnet = Some(value == "true"),
+            "bridge" => self.bridge = Some(value),
+            "pot.cmd" => self.cmd = Some(value),
+            "pot.env" => self.env.push(value),
+            _ => {
+                if let Some(attribute) = key.strip_prefix("pot.attr.") {
+                    self.attributes.insert(attribute.to_string(), value);
+                } else {
+                    self.extras.insert(key.to_string(), value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PotConfig {
+    type Err = PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parsed = PotConfig::parse(s);
+        match parsed.invalid.into_iter().next() {
+            Some((_, _, e)) => Err(e),
+            None => Ok(parsed.config),
+        }
+    }
+}
+
+/// The path of the pot.conf file of the pot `pot_name`
+pub fn get_pot_config_path(conf: &PotSystemConfig, pot_name: &str) -> PathBuf {
+    Path::new(&conf.fs_root)
+        .join("jails")
+        .join(pot_name)
+        .join("conf")
+        .join("pot.conf")
+}
+
+/// Read and parse the pot.conf file of the pot `pot_name`
+pub fn get_pot_config(conf: &PotSystemConfig, pot_name: &str) -> Result<PotConfig> {
+    let conf_str = std::fs::read_to_string(get_pot_config_path(conf, pot_name))?;
+    conf_str.parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pot_config_fromstr_001() {
+        let uut = PotConfig::from_str("").unwrap();
+        assert_eq!(uut, PotConfig::default());
+        let uut = PotConfig::from_str("# pot.level=1\n\n").unwrap();
+        assert_eq!(uut, PotConfig::default());
+    }
+
+    #[test]
+    fn pot_config_fromstr_002() {
+        let uut = PotConfig::from_str(
+            "pot.level=1\npot.potbase=base-12_1\npot.depend=dns\npot.depend=db\n\
+             host.hostname=\"web.pot\"\npot.export.ports=80 443:8443\n\
+             pot.attr.no-rc-script=NO\npot.attr.persistent=YES\npot.stack=ipv4\n\
+             network_type=private-bridge\nip=10.192.0.3\nbridge=backend\n\
+             pot.cmd=sh /etc/rc\npot.env=FOO=bar\npot.env=BAR=baz\nosrelease=12.1\npot.dns=inherit",
+        )
+        .unwrap();
+        assert_eq!(uut.level, Some(1));
+        assert_eq!(uut.potbase, Some("base-12_1".to_string()));
+        assert_eq!(uut.depend, vec!["dns".to_string(), "db".to_string()]);
+        assert_eq!(uut.hostname, Some("web.pot".to_string()));
+        assert_eq!(
+            uut.export_ports,
+            vec!["80".to_string(), "443:8443".to_string()]
+        );
+        assert_eq!(uut.attribute("no-rc-script"), Some("NO"));
+        assert_eq!(uut.attribute("persistent"), Some("YES"));
+        assert_eq!(uut.attribute("start-at-boot"), None);
+        assert_eq!(uut.stack, Some(IpStack::Ipv4));
+        assert_eq!(uut.network_type, Some(NetType::PrivateBridge));
+        assert_eq!(uut.ip, Some("10.192.0.3".to_string()));
+        assert_eq!(uut.bridge, Some("backend".to_string()));
+        assert_eq!(uut.cmd, Some("sh /etc/rc".to_string()));
+        assert_eq!(uut.env, vec!["FOO=bar".to_string(), "BAR=baz".to_string()]);
+        assert_eq!(uut.extras.len(), 2);
+        assert_eq!(uut.extras.get("osrelease"), Some(&"12.1".to_string()));
+        assert_eq!(uut.extras.get("pot.dns"), Some(&"inherit".to_string()));
+    }
+
+    #[test]
+    fn pot_config_fromstr_003() {
+        let uut = PotConfig::from_str("ip4=10.192.0.3\nvnet=true").unwrap();
+        assert_eq!(uut.ip4, Some("10.192.0.3".to_string()));
+        assert_eq!(uut.vnet, Some(true));
+        assert_eq!(uut.network_type, None);
+    }
+
+    #[test]
+    fn pot_config_parse_001() {
+        let uut = PotConfig::parse(
+            "network_type=public-bridge\nip=\"10.192.0.3\" # static\npot.level=one\n\
+             network_type=bridge\n",
+        );
+        assert_eq!(uut.config.ip, Some("10.192.0.3".to_string()));
+        assert_eq!(uut.config.network_type, None);
+        assert_eq!(uut.config.level, None);
+        assert_eq!(uut.lines.get("network_type"), Some(&vec![1, 4]));
+        let invalid: Vec<(&str, usize)> = uut
+            .invalid
+            .iter()
+            .map(|(k, l, _)| (k.as_str(), *l))
+            .collect();
+        assert_eq!(invalid, vec![("pot.level", 3), ("network_type", 4)]);
+    }
+
+    #[test]
+    fn pot_config_fromstr_004() {
+        assert!(PotConfig::from_str("pot.level=one").is_err());
+        assert!(PotConfig::from_str("network_type=bridge").is_err());
+        assert!(PotConfig::from_str("pot.stack=ipv5").is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use crate::error::PotError;
+use crate::lease::get_potnet_dir;
+use crate::util::{lock_exclusive, now, parse_records, read_optional};
+use crate::{PotSystemConfig, Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// An address recently released, not handed out until the quarantine is over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quarantined {
+    pub ip: IpAddr,
+    /// The pot, or the lease owner, the address belonged to
+    pub owner: String,
+    /// Release time, in seconds since the epoch
+    pub released: u64,
+}
+
+impl Quarantined {
+    pub fn is_expired(&self, now: u64, period: u64) -> bool {
+        self.released + period <= now
+    }
+}
+
+impl std::fmt::Display for Quarantined {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.ip, self.owner, self.released)
+    }
+}
+
+/// The quarantine database, stored in `fs_root`/potnet/quarantine
+///
+/// The addresses of the pots seen by the previous run are stored in `fs_root`/potnet/known,
+/// so that the addresses of the pots destroyed in the meantime can be quarantined.
+/// The database is protected by an advisory lock, held until the QuarantineDb is dropped
+#[derive(Debug)]
+pub struct QuarantineDb {
+    dir: PathBuf,
+    entries: Vec<Quarantined>,
+    known: BTreeMap<IpAddr, String>,
+    _lock: File,
+}
+
+// the lines are `<address> <owner> <release time>`
+fn parse_quarantine(path: &Path, s: &str) -> Result<Vec<Quarantined>> {
+    parse_records(
+        path,
+        s,
+        |fields| match fields {
+            [ip, owner, released] => Some(Quarantined {
+                ip: ip.parse().ok()?,
+                owner: owner.to_string(),
+                released: released.parse().ok()?,
+            }),
+            _ => None,
+        },
+        PotError::QuarantineDbError,
+    )
+}
+
+// the lines are `<address> <pot>`
+fn parse_known(path: &Path, s: &str) -> Result<BTreeMap<IpAddr, String>> {
+    let known = parse_records(
+        path,
+        s,
+        |fields| match fields {
+            [ip, pot] => Some((ip.parse().ok()?, pot.to_string())),
+            _ => None,
+        },
+        PotError::QuarantineDbError,
+    )?;
+    Ok(known.into_iter().collect())
+}
+
+/// The addresses still in quarantine, read without taking the lock
+///
+/// Nothing is in quarantine if the period (POT_IP_QUARANTINE) is 0
+pub fn get_quarantined(conf: &PotSystemConfig) -> Result<Vec<Quarantined>> {
+    if conf.ip_quarantine == 0 {
+        return Ok(Vec::new());
+    }
+    let path = get_potnet_dir(conf).join("quarantine");
+    let entries = match read_optional(&path)? {
+        Some(s) => parse_quarantine(&path, &s)?,
+        None => return Ok(Vec::new()),
+    };
+    let now = now();
+    Ok(entries
+        .into_iter()
+        .filter(|q| !q.is_expired(now, conf.ip_quarantine))
+        .collect())
+}
+
+impl QuarantineDb {
+    /// Lock and read the quarantine database of the pot installation
+    pub fn open(conf: &PotSystemConfig) -> Result<Self> {
+        let dir = get_potnet_dir(conf);
+        std::fs::create_dir_all(&dir)?;
+        QuarantineDb::open_dir(&dir, conf.ip_quarantine)
+    }
+
+    /// Lock and read the quarantine database stored in `dir`, dropping the expired entries
+    pub fn open_dir(dir: &Path, period: u64) -> Result<Self> {
+        let lock = lock_exclusive(&dir.join("quarantine.lock"))?;
+        let path = dir.join("quarantine");
+        let entries = match read_optional(&path)? {
+            Some(s) => parse_quarantine(&path, &s)?,
+            None => Vec::new(),
+        };
+        let path = dir.join("known");
+        let known = match read_optional(&path)? {
+            Some(s) => parse_known(&path, &s)?,
+            None => BTreeMap::new(),
+        };
+        let now = now();
+        Ok(QuarantineDb {
+            dir: dir.to_path_buf(),
+            entries: entries
+                .into_iter()
+                .filter(|q| !q.is_expired(now, period))
+                .collect(),
+            known,
+            _lock: lock,
+        })
+    }
+
+    pub fn entries(&self) -> &[Quarantined] {
+        &self.entries
+    }
+
+    /// Put `ip` in quarantine, restarting it if the address is already there
+    pub fn add(&mut self, ip: IpAddr, owner: &str) {
+        self.entries.retain(|q| q.ip != ip);
+        self.entries.push(Quarantined {
+            ip,
+            owner: owner.to_string(),
+            released: now(),
+        });
+    }
+
+    /// Quarantine the addresses of the pots vanished since the previous run,
+    /// and remember the addresses of the current `pots`, as (address, pot name)
+    ///
+    /// The addresses moved to a different pot in the meantime are not quarantined;
+    /// the addresses known for the `unreadable` pots, whose configuration can't be
+    /// understood, are kept
+    pub fn update_pots(
+        &mut self,
+        pots: &[(IpAddr, String)],
+        unreadable: &[&str],
+    ) -> Vec<Quarantined> {
+        let mut current: BTreeMap<IpAddr, String> = self
+            .known
+            .iter()
+            .filter(|(_, pot)| unreadable.contains(&pot.as_str()))
+            .map(|(ip, pot)| (*ip, pot.clone()))
+            .collect();
+        current.extend(pots.iter().cloned());
+        let vanished: Vec<(IpAddr, String)> = self
+            .known
+            .iter()
+            .filter(|(ip, _)| !current.contains_key(ip))
+            .map(|(ip, pot)| (*ip, pot.clone()))
+            .collect();
+        for (ip, pot) in &vanished {
+            self.add(*ip, pot);
+        }
+        self.known = current;
+        self.entries
+            .iter()
+            .filter(|q| vanished.iter().any(|(ip, _)| *ip == q.ip))
+            .cloned()
+            .collect()
+    }
+
+    /// Write the database; the lock is kept until the QuarantineDb is dropped
+    pub fn save(&self) -> Result<()> {
+        let content: String = self.entries.iter().map(|q| format!("{}\n", q)).collect();
+        crate::util::write_atomic(&self.dir.join("quarantine"), content.as_bytes())?;
+        let content: String = self
+            .known
+            .iter()
+            .map(|(ip, pot)| format!("{} {}\n", ip, pot))
+            .collect();
+        crate::util::write_atomic(&self.dir.join("known"), content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_quarantine_001() {
+        let path = Path::new("quarantine");
+        let uut = parse_quarantine(path, "# quarantine\n\n10.192.0.3 foo 1600000000\n").unwrap();
+        assert_eq!(
+            uut,
+            vec![Quarantined {
+                ip: "10.192.0.3".parse().unwrap(),
+                owner: "foo".to_string(),
+                released: 1_600_000_000
+            }]
+        );
+        assert!(uut[0].is_expired(1_600_000_600, 600));
+        assert!(!uut[0].is_expired(1_600_000_599, 600));
+        assert!(parse_quarantine(path, "10.192.0.3 foo").is_err());
+        assert!(parse_known(path, "10.192.0.3").is_err());
+    }
+
+    #[test]
+    fn quarantine_db_001() {
+        let dir = tempfile::tempdir().unwrap();
+        let web = "10.192.0.3".parse().unwrap();
+        let db = "10.192.0.4".parse().unwrap();
+        {
+            let mut uut = QuarantineDb::open_dir(dir.path(), 600).unwrap();
+            assert!(uut
+                .update_pots(&[(web, "web".to_string()), (db, "db".to_string())], &[])
+                .is_empty());
+            uut.save().unwrap();
+        }
+        {
+            // web can't be read: its address is not released
+            let mut uut = QuarantineDb::open_dir(dir.path(), 600).unwrap();
+            assert!(uut
+                .update_pots(&[(db, "db".to_string())], &["web"])
+                .is_empty());
+            uut.save().unwrap();
+        }
+        {
+            let mut uut = QuarantineDb::open_dir(dir.path(), 600).unwrap();
+            let uut = uut.update_pots(&[(db, "db".to_string())], &[]);
+            assert_eq!(uut.len(), 1);
+            assert_eq!(uut[0].ip, web);
+            assert_eq!(uut[0].owner, "web".to_string());
+        }
+        // the previous run has not been saved
+        let mut uut = QuarantineDb::open_dir(dir.path(), 600).unwrap();
+        assert!(uut.entries().is_empty());
+        uut.add(db, "db");
+        uut.save().unwrap();
+        drop(uut);
+        let uut = QuarantineDb::open_dir(dir.path(), 0).unwrap();
+        assert!(uut.entries().is_empty());
+    }
+}
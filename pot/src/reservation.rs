@@ -0,0 +1,91 @@
+use crate::error::PotError;
+use crate::lease::get_potnet_dir;
+use crate::{PotSystemConfig, Result};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// A static address, assigned to a pot by name, that survives its destruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub pot: String,
+    pub ip: IpAddr,
+    /// The private bridge the address belongs to, None for the pot network
+    pub bridge: Option<String>,
+}
+
+/// The reservations file, `fs_root`/potnet/reservations
+///
+/// Every line is `<pot name> <address> [<bridge name>]`; empty lines and comments are ignored
+pub fn get_reservations_path(conf: &PotSystemConfig) -> PathBuf {
+    get_potnet_dir(conf).join("reservations")
+}
+
+fn parse_reservations(path: &Path, s: &str) -> Result<Vec<Reservation>> {
+    let mut result = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = || PotError::ReservationsError(format!("{}:{}", path.display(), i + 1));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (pot, ip, bridge) = match fields.as_slice() {
+            [pot, ip] => (pot, ip, None),
+            [pot, ip, bridge] => (pot, ip, Some(bridge.to_string())),
+            _ => return Err(error()),
+        };
+        let ip = ip.parse().map_err(|_| error())?;
+        result.push(Reservation {
+            pot: pot.to_string(),
+            ip,
+            bridge,
+        });
+    }
+    Ok(result)
+}
+
+/// The static reservations; a missing file is equivalent to an empty one
+pub fn get_reservations(conf: &PotSystemConfig) -> Result<Vec<Reservation>> {
+    let path = get_reservations_path(conf);
+    match std::fs::read_to_string(&path) {
+        Ok(s) => parse_reservations(&path, &s),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reservations_001() {
+        let path = Path::new("reservations");
+        let uut = parse_reservations(
+            path,
+            "# static addresses\n\nweb 10.192.0.10\ndb 10.192.0.131 backend\n",
+        )
+        .unwrap();
+        assert_eq!(
+            uut,
+            vec![
+                Reservation {
+                    pot: "web".to_string(),
+                    ip: "10.192.0.10".parse().unwrap(),
+                    bridge: None,
+                },
+                Reservation {
+                    pot: "db".to_string(),
+                    ip: "10.192.0.131".parse().unwrap(),
+                    bridge: Some("backend".to_string()),
+                }
+            ]
+        );
+        assert!(parse_reservations(path, "web").is_err());
+        assert!(matches!(
+            parse_reservations(path, "web 10.192.0.10\nweb 10.192.0.11 backend extra"),
+            Err(PotError::ReservationsError(s)) if s == "reservations:2"
+        ));
+        assert!(parse_reservations(path, "web 10.192.0").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::error::PotError;
+use crate::Result;
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+
+/// The result of an external command execution
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOutput {
+    /// The exit code, None if the process was killed by a signal
+    pub exit_code: Option<i32>,
+    pub stdout: Vec<u8>,
+}
+
+impl CommandOutput {
+    pub fn new(exit_code: i32, stdout: &str) -> Self {
+        CommandOutput {
+            exit_code: Some(exit_code),
+            stdout: stdout.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Every interaction with host commands (jls, cpuset, sysctl, which) goes through this trait
+pub trait CommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput>;
+}
+
+/// The runner that executes the commands on the host
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput> {
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|_| PotError::CommandError(program.to_string()))?;
+        Ok(CommandOutput {
+            exit_code: output.status.code(),
+            stdout: output.stdout,
+        })
+    }
+}
+
+/// A fake runner, returning canned outputs for the command lines it knows
+///
+/// Command lines without a registered output fail as if the program was not found
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedRunner {
+    outputs: HashMap<String, CommandOutput>,
+}
+
+impl ScriptedRunner {
+    pub fn new() -> Self {
+        ScriptedRunner::default()
+    }
+
+    /// Register the exit code and the stdout returned by `program args`
+    pub fn with_output(
+        mut self,
+        program: &str,
+        args: &[&str],
+        exit_code: i32,
+        stdout: &str,
+    ) -> Self {
+        self.outputs.insert(
+            command_line(program, args),
+            CommandOutput::new(exit_code, stdout),
+        );
+        self
+    }
+}
+
+impl CommandRunner for ScriptedRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput> {
+        self.outputs
+            .get(&command_line(program, args))
+            .cloned()
+            .ok_or_else(|| PotError::CommandError(program.to_string()))
+    }
+}
+
+fn command_line(program: &str, args: &[&str]) -> String {
+    let mut result = program.to_string();
+    for arg in args {
+        result.push(' ');
+        result.push_str(arg);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_runner_001() {
+        let uut = ScriptedRunner::new();
+        assert!(uut.run("/sbin/sysctl", &["-n", "hw.ncpu"]).is_err());
+    }
+
+    #[test]
+    fn scripted_runner_002() {
+        let uut = ScriptedRunner::new().with_output("/sbin/sysctl", &["-n", "hw.ncpu"], 0, "4\n");
+        let output = uut.run("/sbin/sysctl", &["-n", "hw.ncpu"]).unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, b"4\n".to_vec());
+        assert!(uut.run("/sbin/sysctl", &["-n", "hw.physmem"]).is_err());
+    }
+
+    #[test]
+    fn scripted_runner_003() {
+        let uut = ScriptedRunner::new().with_output("/usr/sbin/jls", &["-j", "foo"], 1, "");
+        let output = uut.run("/usr/sbin/jls", &["-j", "foo"]).unwrap();
+        assert!(!output.success());
+        assert_eq!(output.exit_code, Some(1));
+    }
+}
@@ -1,12 +1,33 @@
+use crate::allocation::Strategy;
 use crate::error::PotError;
+use crate::iprange::{parse_range_list, IpRange};
+use crate::runner::CommandRunner;
 use crate::Result;
 use ipnet::IpNet;
+use std::collections::BTreeMap;
 use std::default::Default;
 use std::net::IpAddr;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+// the variables read by potnet
+const SYSTEM_KEYS: &[&str] = &[
+    "POT_ZFS_ROOT",
+    "POT_FS_ROOT",
+    "POT_EXTIF",
+    "POT_DNS_NAME",
+    "POT_NETWORK",
+    "POT_NETMASK",
+    "POT_GATEWAY",
+    "POT_DNS_IP",
+    "POT_NETWORK6",
+    "POT_GATEWAY6",
+    "POT_DNS_IP6",
+    "POT_NETWORK_RESERVED",
+    "POT_IP_ALLOCATION",
+    "POT_IP_QUARANTINE",
+];
+
 #[derive(Default, Debug, Clone, PartialEq)]
 pub(crate) struct PartialSystemConf {
     pub(crate) zfs_root: Option<String>,
@@ -17,23 +38,115 @@ pub(crate) struct PartialSystemConf {
     pub(crate) ext_if: Option<String>,
     pub(crate) dns_name: Option<String>,
     pub(crate) dns_ip: Option<IpAddr>,
+    pub(crate) network6: Option<IpNet>,
+    pub(crate) gateway6: Option<IpAddr>,
+    pub(crate) dns_ip6: Option<IpAddr>,
+    pub(crate) network_reserved: Option<Vec<IpRange>>,
+    pub(crate) ip_allocation: Option<Strategy>,
+    pub(crate) ip_quarantine: Option<u64>,
+    /// All the variables assigned, expanded, used to expand the following assignments
+    pub(crate) vars: BTreeMap<String, String>,
 }
 
 impl PartialSystemConf {
-    pub fn new() -> PartialSystemConf {
-        let s = match get_conf_default() {
+    /// Read the default configuration file, overridden by the user one, if present
+    pub fn from_files(default: &Path, user: &Path) -> Result<PartialSystemConf> {
+        let s = std::fs::read_to_string(default)?;
+        let mut dconf = PartialSystemConf::from_str(&s)?;
+        let s = match std::fs::read_to_string(user) {
             Ok(s) => s,
-            Err(_) => return PartialSystemConf::default(),
+            Err(_) => return Ok(dconf),
         };
+        let pconf = PartialSystemConf::parse(&s, &dconf.vars)?;
+        dconf.merge(pconf);
+        Ok(dconf)
+    }
 
-        let mut dconf = PartialSystemConf::from_str(&s).ok().unwrap_or_default();
-        let s = match get_conf() {
-            Ok(s) => s,
-            Err(_) => return dconf,
+    /// Parse a configuration file, expanding variables with the ones in `vars`,
+    /// then with the environment
+    ///
+    /// An expansion not supported is an error only for the variables used by potnet,
+    /// the assignments of the other ones are skipped
+    pub fn parse(s: &str, vars: &BTreeMap<String, String>) -> Result<PartialSystemConf> {
+        use crate::util::{assignment_key, parse_assignment_expanded};
+        let mut default = PartialSystemConf {
+            vars: vars.clone(),
+            ..PartialSystemConf::default()
         };
-        let pconf = PartialSystemConf::from_str(&s).ok().unwrap_or_default();
-        dconf.merge(pconf);
-        dconf
+        for linestr in s.lines() {
+            let lookup = |name: &str| {
+                default
+                    .vars
+                    .get(name)
+                    .cloned()
+                    .or_else(|| std::env::var(name).ok())
+            };
+            let (key, value) = match parse_assignment_expanded(linestr, &lookup) {
+                Ok(Some(x)) => x,
+                Ok(None) => continue,
+                Err(e) => match assignment_key(linestr) {
+                    Some(key) if SYSTEM_KEYS.contains(&key) => return Err(e),
+                    _ => continue,
+                },
+            };
+            match key.as_str() {
+                "POT_ZFS_ROOT" => default.zfs_root = value.parse().ok(),
+                "POT_FS_ROOT" => default.fs_root = value.parse().ok(),
+                "POT_EXTIF" => default.ext_if = value.parse().ok(),
+                "POT_DNS_NAME" => default.dns_name = value.parse().ok(),
+                "POT_NETWORK" => default.network = value.parse().ok(),
+                "POT_NETMASK" => default.netmask = value.parse().ok(),
+                "POT_GATEWAY" => default.gateway = value.parse().ok(),
+                "POT_DNS_IP" => default.dns_ip = value.parse().ok(),
+                // the IPv6 keys accept only IPv6 values
+                // an IPv4 or invalid value for an IPv6 key is an error, as the network is unusable
+                "POT_NETWORK6" => {
+                    default.network6 = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|n| matches!(n, IpNet::V6(_)))
+                            .ok_or_else(|| PotError::ConfValueError(key.clone(), value.clone()))?,
+                    )
+                }
+                "POT_GATEWAY6" => {
+                    default.gateway6 = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|ip: &IpAddr| ip.is_ipv6())
+                            .ok_or_else(|| PotError::ConfValueError(key.clone(), value.clone()))?,
+                    )
+                }
+                "POT_DNS_IP6" => {
+                    default.dns_ip6 = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|ip: &IpAddr| ip.is_ipv6())
+                            .ok_or_else(|| PotError::ConfValueError(key.clone(), value.clone()))?,
+                    )
+                }
+                // an invalid range is an error: ignoring it would hand out reserved addresses
+                "POT_NETWORK_RESERVED" => {
+                    default.network_reserved = Some(
+                        parse_range_list(&value)
+                            .map_err(|_| PotError::ConfValueError(key.clone(), value.clone()))?,
+                    )
+                }
+                "POT_IP_ALLOCATION" => default.ip_allocation = Some(value.parse()?),
+                "POT_IP_QUARANTINE" => {
+                    default.ip_quarantine = Some(
+                        value
+                            .parse()
+                            .map_err(|_| PotError::ConfValueError(key.clone(), value.clone()))?,
+                    )
+                }
+                _ => (),
+            }
+            default.vars.insert(key, value);
+        }
+        Ok(default)
     }
 
     pub fn is_valid(&self) -> bool {
@@ -45,14 +158,17 @@ impl PartialSystemConf {
             && self.ext_if.is_some()
             && self.dns_name.is_some()
             && self.dns_ip.is_some()
+            // the IPv6 pool is optional, but it needs its gateway
+            && self.network6.is_some() == self.gateway6.is_some()
+            && (self.dns_ip6.is_none() || self.network6.is_some())
     }
 
     fn merge(&mut self, rhs: PartialSystemConf) {
-        if rhs.zfs_root.is_some() {
-            self.zfs_root = Some(rhs.zfs_root.unwrap());
+        if let Some(s) = rhs.zfs_root {
+            self.zfs_root = Some(s);
         }
-        if rhs.fs_root.is_some() {
-            self.fs_root = Some(rhs.fs_root.unwrap());
+        if let Some(s) = rhs.fs_root {
+            self.fs_root = Some(s);
         }
         self.network = match rhs.network {
             Some(s) => Some(s),
@@ -66,91 +182,125 @@ impl PartialSystemConf {
             Some(s) => Some(s),
             None => self.gateway,
         };
-        if rhs.ext_if.is_some() {
-            self.ext_if = Some(rhs.ext_if.unwrap());
+        if let Some(s) = rhs.ext_if {
+            self.ext_if = Some(s);
         }
-        if rhs.dns_name.is_some() {
-            self.dns_name = Some(rhs.dns_name.unwrap());
+        if let Some(s) = rhs.dns_name {
+            self.dns_name = Some(s);
         }
         self.dns_ip = match rhs.dns_ip {
             Some(s) => Some(s),
             None => self.dns_ip,
         };
+        if let Some(s) = rhs.network6 {
+            self.network6 = Some(s);
+        }
+        if let Some(s) = rhs.gateway6 {
+            self.gateway6 = Some(s);
+        }
+        if let Some(s) = rhs.dns_ip6 {
+            self.dns_ip6 = Some(s);
+        }
+        if let Some(s) = rhs.network_reserved {
+            self.network_reserved = Some(s);
+        }
+        if let Some(s) = rhs.ip_allocation {
+            self.ip_allocation = Some(s);
+        }
+        if let Some(s) = rhs.ip_quarantine {
+            self.ip_quarantine = Some(s);
+        }
+        self.vars.extend(rhs.vars);
     }
 }
 
 impl FromStr for PartialSystemConf {
     type Err = PotError;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        use crate::util::get_value;
-        let mut default = PartialSystemConf::default();
-        let lines: Vec<String> = s
-            .to_string()
-            .lines()
-            .map(|x| x.trim().to_string())
-            .filter(|x| !x.starts_with('#'))
-            .collect();
-        for linestr in &lines {
-            if linestr.starts_with("POT_ZFS_ROOT=") {
-                default.zfs_root = get_value(linestr);
-            }
-            if linestr.starts_with("POT_FS_ROOT=") {
-                default.fs_root = get_value(linestr);
-            }
-            if linestr.starts_with("POT_EXTIF=") {
-                default.ext_if = get_value(linestr);
-            }
-            if linestr.starts_with("POT_DNS_NAME=") {
-                default.dns_name = get_value(linestr);
-            }
-            if linestr.starts_with("POT_NETWORK=") {
-                default.network = get_value(linestr);
-            }
-            if linestr.starts_with("POT_NETMASK=") {
-                default.netmask = get_value(linestr);
-            }
-            if linestr.starts_with("POT_GATEWAY=") {
-                default.gateway = get_value(linestr);
+        PartialSystemConf::parse(s, &BTreeMap::new())
+    }
+}
+
+/// How the configuration files have been found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// Explicit prefix (e.g. --prefix)
+    Prefix(PathBuf),
+    /// Prefix provided by the POT_PREFIX environment variable
+    PrefixEnv(PathBuf),
+    /// Prefix derived from the pot executable found in PATH
+    Which(PathBuf),
+    /// Explicit user configuration file (e.g. --config), pot.default.conf is in the same directory
+    Conf(PathBuf),
+    /// User configuration file provided by the POT_CONF environment variable
+    ConfEnv(PathBuf),
+    /// Explicit default and user configuration files
+    Files { default: PathBuf, user: PathBuf },
+}
+
+impl ConfigSource {
+    /// The default and the user configuration files
+    pub fn conf_files(&self) -> (PathBuf, PathBuf) {
+        match self {
+            ConfigSource::Prefix(prefix)
+            | ConfigSource::PrefixEnv(prefix)
+            | ConfigSource::Which(prefix) => {
+                let conf_dir = prefix.join("etc").join("pot");
+                (conf_dir.join("pot.default.conf"), conf_dir.join("pot.conf"))
             }
-            if linestr.starts_with("POT_DNS_IP=") {
-                default.dns_ip = get_value(linestr);
+            ConfigSource::Conf(user) | ConfigSource::ConfEnv(user) => {
+                let conf_dir = user.parent().unwrap_or_else(|| Path::new("."));
+                (conf_dir.join("pot.default.conf"), user.clone())
             }
+            ConfigSource::Files { default, user } => (default.clone(), user.clone()),
         }
-        Ok(default)
     }
 }
 
-pub(crate) fn get_conf_default() -> Result<String> {
-    let mut pot_conf = get_pot_prefix()?;
-    pot_conf.push("etc");
-    pot_conf.push("pot");
-    pot_conf.push("pot.default.conf");
-
-    let result = std::fs::read_to_string(pot_conf)?;
-    Ok(result)
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::Prefix(p) => write!(f, "prefix {} (explicit)", p.display()),
+            ConfigSource::PrefixEnv(p) => write!(f, "prefix {} (POT_PREFIX)", p.display()),
+            ConfigSource::Which(p) => write!(f, "prefix {} (pot found in PATH)", p.display()),
+            ConfigSource::Conf(c) => write!(f, "config {} (explicit)", c.display()),
+            ConfigSource::ConfEnv(c) => write!(f, "config {} (POT_CONF)", c.display()),
+            ConfigSource::Files { default, user } => write!(
+                f,
+                "default config {} and config {} (explicit)",
+                default.display(),
+                user.display()
+            ),
+        }
+    }
 }
 
-pub(crate) fn get_conf() -> Result<String> {
-    let mut pot_conf = get_pot_prefix()?;
-    pot_conf.push("etc");
-    pot_conf.push("pot");
-    pot_conf.push("pot.conf");
-
-    let result = std::fs::read_to_string(pot_conf)?;
-    Ok(result)
+// look for the configuration files in this order:
+// POT_CONF, POT_PREFIX and then the prefix where the pot executable is installed
+pub(crate) fn locate_conf(
+    runner: &dyn CommandRunner,
+    pot_conf: Option<PathBuf>,
+    pot_prefix: Option<PathBuf>,
+) -> Result<ConfigSource> {
+    if let Some(pot_conf) = pot_conf.filter(|x| !x.as_os_str().is_empty()) {
+        return Ok(ConfigSource::ConfEnv(pot_conf));
+    }
+    if let Some(pot_prefix) = pot_prefix.filter(|x| !x.as_os_str().is_empty()) {
+        return Ok(ConfigSource::PrefixEnv(pot_prefix));
+    }
+    Ok(ConfigSource::Which(get_pot_prefix(runner)?))
 }
 
 // get pot prefix in the same way as pot does:
 // find PREFIX/bin/pot and get the PREFIX
-fn get_pot_prefix() -> Result<PathBuf> {
-    let pathname = Command::new("which")
-        .arg("pot")
-        .output()
+fn get_pot_prefix(runner: &dyn CommandRunner) -> Result<PathBuf> {
+    let pathname = runner
+        .run("which", &["pot"])
         .map_err(|_| PotError::WhichError("pot".to_string()))?;
-    if !pathname.status.success() {
+    if !pathname.success() {
         return Err(PotError::WhichError("pot".to_string()));
     }
-    let pot_path = PathBuf::from(String::from_utf8(pathname.stdout)?);
+    let pot_path = PathBuf::from(String::from_utf8(pathname.stdout)?.trim());
     let pot_prefix = pot_path
         .parent()
         .ok_or_else(|| PotError::PathError(format!("{}", pot_path.display())))?;
@@ -160,14 +310,15 @@ fn get_pot_prefix() -> Result<PathBuf> {
     Ok(pot_prefix.to_path_buf())
 }
 #[cfg(test)]
-
 mod tests {
     use super::*;
+    use crate::runner::ScriptedRunner;
+    use std::convert::TryFrom;
 
     #[test]
     fn partial_system_conf_default() {
         let uut = PartialSystemConf::default();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_eq!(uut.dns_ip, None);
         assert_eq!(uut.dns_name, None);
         assert_eq!(uut.ext_if, None);
@@ -181,38 +332,38 @@ mod tests {
     #[test]
     fn partial_system_conf_fromstr_001() {
         let uut = PartialSystemConf::from_str("");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_eq!(uut, PartialSystemConf::default());
     }
 
     #[test]
     fn partial_system_conf_fromstr_002() {
         let uut = PartialSystemConf::from_str("# Comment 1\n # Comment with space");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_eq!(uut, PartialSystemConf::default());
     }
 
     #[test]
     fn partial_system_conf_fromstr_003() {
         let uut = PartialSystemConf::from_str(" # POT_GATEWAY=192.168.0.1");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_eq!(uut, PartialSystemConf::default());
     }
 
     #[test]
     fn partial_system_conf_fromstr_004() {
         let uut = PartialSystemConf::from_str("POT_GATEWAY=192.168.0.1");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.gateway.is_some(), true);
+        assert!(uut.gateway.is_some());
         assert_eq!(
             uut.gateway.unwrap(),
             "192.168.0.1".parse::<IpAddr>().unwrap()
@@ -222,20 +373,20 @@ mod tests {
     #[test]
     fn partial_system_conf_fromstr_005() {
         let uut = PartialSystemConf::from_str("POT_NETWORK=192.168.0.0");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
-        assert_eq!(uut.network.is_some(), false);
+        assert!(!uut.is_valid());
+        assert!(uut.network.is_none());
     }
 
     #[test]
     fn partial_system_conf_fromstr_006() {
         let uut = PartialSystemConf::from_str("POT_NETWORK=192.168.0.0/24");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.network.is_some(), true);
+        assert!(uut.network.is_some());
         assert_eq!(
             uut.network.unwrap(),
             "192.168.0.0/24".parse::<IpNet>().unwrap()
@@ -245,44 +396,44 @@ mod tests {
     #[test]
     fn partial_system_conf_fromstr_007() {
         let uut = PartialSystemConf::from_str("POT_DNS_NAME=FOO_DNS");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.dns_name.is_some(), true);
+        assert!(uut.dns_name.is_some());
         assert_eq!(uut.dns_name.unwrap(), "FOO_DNS".to_string());
     }
 
     #[test]
     fn partial_system_conf_fromstr_008() {
         let uut = PartialSystemConf::from_str("POT_DNS_NAME=\"FOO_DNS\"");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.dns_name.is_some(), true);
-        assert_ne!(uut.dns_name.unwrap(), "FOO_DNS".to_string());
+        assert!(uut.dns_name.is_some());
+        assert_eq!(uut.dns_name.unwrap(), "FOO_DNS".to_string());
     }
 
     #[test]
     fn partial_system_conf_fromstr_009() {
         let uut = PartialSystemConf::from_str("POT_DNS_NAME=FOO_DNS # dns pot name");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.dns_name.is_some(), true);
+        assert!(uut.dns_name.is_some());
         assert_eq!(uut.dns_name.unwrap(), "FOO_DNS".to_string());
     }
 
     #[test]
     fn partial_system_conf_fromstr_010() {
         let uut = PartialSystemConf::from_str("POT_DNS_IP=192.168.240.240 # dns pot ip");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.dns_ip.is_some(), true);
+        assert!(uut.dns_ip.is_some());
         assert_eq!(
             uut.dns_ip.unwrap(),
             "192.168.240.240".parse::<IpAddr>().unwrap()
@@ -292,11 +443,11 @@ mod tests {
     #[test]
     fn partial_system_conf_fromstr_011() {
         let uut = PartialSystemConf::from_str("POT_NETWORK=192.168.0.0/22 # pots internal network");
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.network.is_some(), true);
+        assert!(uut.network.is_some());
         assert_eq!(
             uut.network.unwrap(),
             "192.168.0.0/22".parse::<IpNet>().unwrap()
@@ -308,17 +459,79 @@ mod tests {
         let uut = PartialSystemConf::from_str(
             "POT_NETWORK=fdf1:186e:49e6:76d8::/64 # pots internal network",
         );
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), false);
+        assert!(!uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.network.is_some(), true);
+        assert!(uut.network.is_some());
         assert_eq!(
             uut.network.unwrap(),
             "fdf1:186e:49e6:76d8::/64".parse::<IpNet>().unwrap()
         );
     }
 
+    #[test]
+    fn partial_system_conf_fromstr_013() {
+        let uut = PartialSystemConf::from_str(
+            "export POT_DNS_NAME='foo dns'\nPOT_EXTIF=em0\t# external interface\n\
+             POT_ZFS_ROOT=\"zroot/pot # not a comment\"\nPOT_FS_ROOT=/opt/pot\\ dir",
+        )
+        .unwrap();
+        assert_eq!(uut.dns_name, Some("foo dns".to_string()));
+        assert_eq!(uut.ext_if, Some("em0".to_string()));
+        assert_eq!(uut.zfs_root, Some("zroot/pot # not a comment".to_string()));
+        assert_eq!(uut.fs_root, Some("/opt/pot dir".to_string()));
+    }
+
+    #[test]
+    fn partial_system_conf_fromstr_014() {
+        let uut = PartialSystemConf::from_str("POT_DNS_NAME=\"foo_dns\nPOT_EXTIF=em0").unwrap();
+        assert_eq!(uut.dns_name, None);
+        assert_eq!(uut.ext_if, Some("em0".to_string()));
+    }
+
+    #[test]
+    fn partial_system_conf_fromstr_015() {
+        let uut = PartialSystemConf::from_str(
+            "POT_ROOT=/opt/pot\nPOT_FS_ROOT=${POT_ROOT}/fs\nPOT_CACHE=$POT_FS_ROOT/cache\n\
+             POT_ZFS_ROOT='${POT_ROOT}'",
+        )
+        .unwrap();
+        assert_eq!(uut.fs_root, Some("/opt/pot/fs".to_string()));
+        assert_eq!(uut.zfs_root, Some("${POT_ROOT}".to_string()));
+        assert_eq!(
+            uut.vars.get("POT_CACHE"),
+            Some(&"/opt/pot/fs/cache".to_string())
+        );
+    }
+
+    #[test]
+    fn partial_system_conf_fromstr_016() {
+        let uut = PartialSystemConf::from_str("POT_FS_ROOT=${POT_FS_ROOT}/fs");
+        assert!(uut.is_err());
+        let uut = PartialSystemConf::from_str("POT_FS_ROOT=${POT_TEST_UNDEFINED");
+        assert!(uut.is_err());
+    }
+
+    #[test]
+    fn partial_system_conf_fromstr_017() {
+        // variables not used by potnet can use any expansion
+        let uut = PartialSystemConf::from_str(
+            "POT_CACHE=${POT_CACHE:-/var/cache/pot}\nPOT_TMP=${POT_TMP}/tmp\nPOT_EXTIF=em0",
+        )
+        .unwrap();
+        assert_eq!(uut.ext_if, Some("em0".to_string()));
+        assert_eq!(uut.vars.get("POT_CACHE"), None);
+        // the variables not assigned in the file are taken from the environment
+        std::env::set_var("POTNET_TEST_HOME_017", "/home/pot");
+        let uut = PartialSystemConf::from_str(
+            "POT_FS_ROOT=$POTNET_TEST_HOME_017/pot\nPOT_ZFS_ROOT=${POTNET_TEST_UNSET_017}zroot",
+        )
+        .unwrap();
+        assert_eq!(uut.fs_root, Some("/home/pot/pot".to_string()));
+        assert_eq!(uut.zfs_root, Some("zroot".to_string()));
+    }
+
     #[test]
     fn partial_system_conf_fromstr_050() {
         let uut = PartialSystemConf::from_str(
@@ -326,37 +539,37 @@ mod tests {
             POT_NETWORK=192.168.0.0/24\nPOT_NETMASK=255.255.255.0\nPOT_GATEWAY=192.168.0.1\n
             POT_DNS_IP=192.168.0.2\nPOT_DNS_NAME=bar_dns",
         );
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), true);
+        assert!(uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.network.is_some(), true);
+        assert!(uut.network.is_some());
         assert_eq!(
             uut.network.unwrap(),
             "192.168.0.0/24".parse::<IpNet>().unwrap()
         );
-        assert_eq!(uut.netmask.is_some(), true);
+        assert!(uut.netmask.is_some());
         assert_eq!(
             uut.netmask.unwrap(),
             "255.255.255.0".parse::<IpAddr>().unwrap()
         );
-        assert_eq!(uut.gateway.is_some(), true);
+        assert!(uut.gateway.is_some());
         assert_eq!(
             uut.gateway.unwrap(),
             "192.168.0.1".parse::<IpAddr>().unwrap()
         );
-        assert_eq!(uut.dns_ip.is_some(), true);
+        assert!(uut.dns_ip.is_some());
         assert_eq!(
             uut.dns_ip.unwrap(),
             "192.168.0.2".parse::<IpAddr>().unwrap()
         );
-        assert_eq!(uut.zfs_root.is_some(), true);
+        assert!(uut.zfs_root.is_some());
         assert_eq!(uut.zfs_root.unwrap(), "zroot/pot".to_string());
-        assert_eq!(uut.fs_root.is_some(), true);
+        assert!(uut.fs_root.is_some());
         assert_eq!(uut.fs_root.unwrap(), "/opt/pot".to_string());
-        assert_eq!(uut.ext_if.is_some(), true);
+        assert!(uut.ext_if.is_some());
         assert_eq!(uut.ext_if.unwrap(), "em0".to_string());
-        assert_eq!(uut.dns_name.is_some(), true);
+        assert!(uut.dns_name.is_some());
         assert_eq!(uut.dns_name.unwrap(), "bar_dns".to_string());
     }
 
@@ -367,40 +580,131 @@ mod tests {
             POT_NETWORK=fdf1:186e:49e6:76d8::/64\nPOT_NETMASK=ffff:ffff:ffff:ffff::\nPOT_GATEWAY=fdf1:186e:49e6:76d8::1\n
             POT_DNS_IP=fdf1:186e:49e6:76d8::2\nPOT_DNS_NAME=bar_dns",
         );
-        assert_eq!(uut.is_ok(), true);
+        assert!(uut.is_ok());
         let uut = uut.unwrap();
-        assert_eq!(uut.is_valid(), true);
+        assert!(uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
-        assert_eq!(uut.network.is_some(), true);
+        assert!(uut.network.is_some());
         assert_eq!(
             uut.network.unwrap(),
             "fdf1:186e:49e6:76d8::/64".parse::<IpNet>().unwrap()
         );
-        assert_eq!(uut.netmask.is_some(), true);
+        assert!(uut.netmask.is_some());
         assert_eq!(
             uut.netmask.unwrap(),
             "ffff:ffff:ffff:ffff::".parse::<IpAddr>().unwrap()
         );
-        assert_eq!(uut.gateway.is_some(), true);
+        assert!(uut.gateway.is_some());
         assert_eq!(
             uut.gateway.unwrap(),
             "fdf1:186e:49e6:76d8::1".parse::<IpAddr>().unwrap()
         );
-        assert_eq!(uut.dns_ip.is_some(), true);
+        assert!(uut.dns_ip.is_some());
         assert_eq!(
             uut.dns_ip.unwrap(),
             "fdf1:186e:49e6:76d8::2".parse::<IpAddr>().unwrap()
         );
-        assert_eq!(uut.zfs_root.is_some(), true);
+        assert!(uut.zfs_root.is_some());
         assert_eq!(uut.zfs_root.unwrap(), "zroot/pot".to_string());
-        assert_eq!(uut.fs_root.is_some(), true);
+        assert!(uut.fs_root.is_some());
         assert_eq!(uut.fs_root.unwrap(), "/opt/pot".to_string());
-        assert_eq!(uut.ext_if.is_some(), true);
+        assert!(uut.ext_if.is_some());
         assert_eq!(uut.ext_if.unwrap(), "em0".to_string());
-        assert_eq!(uut.dns_name.is_some(), true);
+        assert!(uut.dns_name.is_some());
         assert_eq!(uut.dns_name.unwrap(), "bar_dns".to_string());
     }
 
+    #[test]
+    fn partial_system_conf_fromstr_052() {
+        let conf = "POT_ZFS_ROOT=zroot/pot\nPOT_FS_ROOT=/opt/pot\nPOT_EXTIF=em0\n
+            POT_NETWORK=192.168.0.0/24\nPOT_NETMASK=255.255.255.0\nPOT_GATEWAY=192.168.0.1\n
+            POT_DNS_IP=192.168.0.2\nPOT_DNS_NAME=bar_dns\n";
+        let uut = PartialSystemConf::from_str(&format!(
+            "{}POT_NETWORK6=fdf1:186e:49e6:76d8::/64\nPOT_GATEWAY6=fdf1:186e:49e6:76d8::1\n\
+             POT_DNS_IP6=fdf1:186e:49e6:76d8::2",
+            conf
+        ))
+        .unwrap();
+        assert!(uut.is_valid());
+        assert_eq!(
+            uut.network6,
+            Some("fdf1:186e:49e6:76d8::/64".parse::<IpNet>().unwrap())
+        );
+        assert_eq!(
+            uut.gateway6,
+            Some("fdf1:186e:49e6:76d8::1".parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(
+            uut.dns_ip6,
+            Some("fdf1:186e:49e6:76d8::2".parse::<IpAddr>().unwrap())
+        );
+        let uut =
+            PartialSystemConf::from_str(&format!("{}POT_NETWORK6=fdf1:186e:49e6:76d8::/64", conf))
+                .unwrap();
+        assert!(!uut.is_valid());
+        let uut =
+            PartialSystemConf::from_str(&format!("{}POT_DNS_IP6=fdf1:186e:49e6:76d8::2", conf))
+                .unwrap();
+        assert!(!uut.is_valid());
+        assert!(matches!(
+            crate::PotSystemConfig::try_from(uut),
+            Err(PotError::MissingSystemConfValue(
+                "POT_NETWORK6",
+                "POT_DNS_IP6"
+            ))
+        ));
+        let uut =
+            PartialSystemConf::from_str(&format!("{}POT_NETWORK6=fdf1:186e:49e6:76d8::/64", conf))
+                .unwrap();
+        assert!(matches!(
+            crate::PotSystemConfig::try_from(uut),
+            Err(PotError::MissingSystemConfValue(
+                "POT_GATEWAY6",
+                "POT_NETWORK6"
+            ))
+        ));
+        // IPv4 or invalid values are errors for the IPv6 keys
+        for line in &[
+            "POT_NETWORK6=10.1.0.0/24",
+            "POT_GATEWAY6=10.1.0.1",
+            "POT_DNS_IP6=10.1.0.2",
+            "POT_NETWORK6=fdf1:186e:49e6:76d8::/129",
+            "POT_GATEWAY6=fdf1::186e::1",
+            "POT_DNS_IP6=dns",
+        ] {
+            let (key, value) = line.split_once('=').unwrap();
+            let result = PartialSystemConf::from_str(&format!("{}{}", conf, line));
+            assert!(
+                matches!(&result, Err(PotError::ConfValueError(k, v)) if k == key && v == value),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn partial_system_conf_fromstr_053() {
+        let uut = PartialSystemConf::from_str(
+            "POT_NETWORK_RESERVED=10.192.0.2-10.192.0.20,10.192.0.64/28",
+        )
+        .unwrap();
+        assert_eq!(
+            uut.network_reserved,
+            Some(vec![
+                "10.192.0.2-10.192.0.20".parse().unwrap(),
+                "10.192.0.64/28".parse().unwrap()
+            ])
+        );
+        let uut = PartialSystemConf::from_str("POT_NETWORK_RESERVED=10.192.0.2-10.192.0");
+        assert!(uut.is_err());
+        let uut = PartialSystemConf::from_str("POT_IP_ALLOCATION=round-robin").unwrap();
+        assert_eq!(uut.ip_allocation, Some(Strategy::RoundRobin));
+        assert!(PartialSystemConf::from_str("POT_IP_ALLOCATION=first").is_err());
+        let uut = PartialSystemConf::from_str("POT_IP_QUARANTINE=1200").unwrap();
+        assert_eq!(uut.ip_quarantine, Some(1200));
+        assert!(PartialSystemConf::from_str("POT_IP_QUARANTINE=20m").is_err());
+    }
+
     #[test]
     fn partial_system_conf_merge_001() {
         let mut uut = PartialSystemConf::default();
@@ -414,6 +718,97 @@ mod tests {
         assert_eq!(uut, uut2);
     }
 
+    #[test]
+    fn get_pot_prefix_001() {
+        let runner =
+            ScriptedRunner::new().with_output("which", &["pot"], 0, "/usr/local/bin/pot\n");
+        let uut = get_pot_prefix(&runner);
+        assert!(uut.is_ok());
+        assert_eq!(uut.unwrap(), PathBuf::from("/usr/local"));
+    }
+
+    #[test]
+    fn get_pot_prefix_002() {
+        let runner = ScriptedRunner::new().with_output("which", &["pot"], 1, "");
+        let uut = get_pot_prefix(&runner);
+        assert!(uut.is_err());
+        let uut = get_pot_prefix(&ScriptedRunner::new());
+        assert!(uut.is_err());
+    }
+
+    #[test]
+    fn locate_conf_001() {
+        let runner =
+            ScriptedRunner::new().with_output("which", &["pot"], 0, "/usr/local/bin/pot\n");
+        let uut = locate_conf(&runner, None, None).unwrap();
+        assert_eq!(uut, ConfigSource::Which(PathBuf::from("/usr/local")));
+        assert_eq!(
+            uut.conf_files(),
+            (
+                PathBuf::from("/usr/local/etc/pot/pot.default.conf"),
+                PathBuf::from("/usr/local/etc/pot/pot.conf")
+            )
+        );
+    }
+
+    #[test]
+    fn locate_conf_002() {
+        let runner = ScriptedRunner::new();
+        let uut = locate_conf(&runner, None, Some(PathBuf::from("/opt/pot"))).unwrap();
+        assert_eq!(uut, ConfigSource::PrefixEnv(PathBuf::from("/opt/pot")));
+        let uut = locate_conf(
+            &runner,
+            Some(PathBuf::from("/etc/pot/pot.conf")),
+            Some(PathBuf::from("/opt/pot")),
+        )
+        .unwrap();
+        assert_eq!(
+            uut,
+            ConfigSource::ConfEnv(PathBuf::from("/etc/pot/pot.conf"))
+        );
+        assert_eq!(
+            uut.conf_files(),
+            (
+                PathBuf::from("/etc/pot/pot.default.conf"),
+                PathBuf::from("/etc/pot/pot.conf")
+            )
+        );
+    }
+
+    #[test]
+    fn locate_conf_003() {
+        let runner = ScriptedRunner::new();
+        assert!(locate_conf(&runner, None, None).is_err());
+        assert!(locate_conf(&runner, Some(PathBuf::new()), Some(PathBuf::new())).is_err());
+    }
+
+    #[test]
+    fn partial_system_conf_from_files_001() {
+        let dir = tempfile::tempdir().unwrap();
+        let default = dir.path().join("pot.default.conf");
+        let user = dir.path().join("pot.conf");
+        assert!(PartialSystemConf::from_files(&default, &user).is_err());
+        std::fs::write(
+            &default,
+            "POT_ZFS_ROOT=zroot/pot\nPOT_FS_ROOT=/opt/pot\nPOT_DNS_NAME=bar_dns\n",
+        )
+        .unwrap();
+        let uut = PartialSystemConf::from_files(&default, &user).unwrap();
+        assert_eq!(uut.dns_name, Some("bar_dns".to_string()));
+        std::fs::write(&user, "POT_DNS_NAME=foo_dns\n").unwrap();
+        let uut = PartialSystemConf::from_files(&default, &user).unwrap();
+        assert_eq!(uut.dns_name, Some("foo_dns".to_string()));
+        assert_eq!(uut.fs_root, Some("/opt/pot".to_string()));
+        std::fs::write(
+            &user,
+            "POT_ZFS_ROOT=${POT_ZFS_ROOT}/data\nPOT_FS_ROOT=$POT_FS_ROOT/data",
+        )
+        .unwrap();
+        let uut = PartialSystemConf::from_files(&default, &user).unwrap();
+        assert_eq!(uut.zfs_root, Some("zroot/pot/data".to_string()));
+        assert_eq!(uut.fs_root, Some("/opt/pot/data".to_string()));
+    }
+
     #[test]
     fn partial_system_conf_merge_002() {
         let mut uut = PartialSystemConf::from_str(
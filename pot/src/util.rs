@@ -1,14 +1,407 @@
-use std::str::FromStr;
-
-pub(crate) fn get_value<T>(line: &str) -> Option<T>
-where
-    T: FromStr,
-{
-    match line.split('=').nth(1) {
-        Some(value) => match value.split(' ').next() {
-            Some(value) => value.parse().ok(),
-            None => None,
-        },
-        None => None,
+use crate::error::PotError;
+use crate::Result;
+use fs2::FileExt;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type Lookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+// parse a line as /bin/sh would do with an assignment: `[export ]KEY=value [# comment]`
+// the value is a single word, where quotes and backslashes are interpreted;
+// None if the line is not an assignment or the quoting is broken
+pub(crate) fn parse_assignment(line: &str) -> Option<(String, String)> {
+    let (key, value) = split_assignment(line)?;
+    let value = parse_word(key, value, None).ok()??;
+    Some((key.to_string(), value))
+}
+
+// as parse_assignment, but $VAR and ${VAR} are expanded, outside of single quotes,
+// using `lookup`; undefined variables are expanded to the empty string, as the shell does,
+// unless it's the variable being assigned
+pub(crate) fn parse_assignment_expanded(
+    line: &str,
+    lookup: Lookup,
+) -> Result<Option<(String, String)>> {
+    let (key, value) = match split_assignment(line) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    Ok(parse_word(key, value, Some(lookup))?.map(|value| (key.to_string(), value)))
+}
+
+// the value of an assignment, as parse_assignment_words interprets it
+pub(crate) fn parse_value(s: &str) -> Option<String> {
+    let mut words = Vec::new();
+    let mut rest = s;
+    loop {
+        let (word, tail) = parse_word_at("", rest, None).ok()??;
+        if !word.is_empty() {
+            words.push(word);
+        }
+        rest = tail.trim_start();
+        if rest.is_empty() || rest.starts_with('#') {
+            break;
+        }
+    }
+    Some(words.join(" "))
+}
+
+// as parse_assignment, but the value is made of all the words up to a comment, unquoted and
+// separated by a space, as pot writes values like `pot.cmd=sh /etc/rc` in pot.conf
+pub(crate) fn parse_assignment_words(line: &str) -> Option<(String, String)> {
+    let (key, value) = split_assignment(line)?;
+    Some((key.to_string(), parse_value(value)?))
+}
+
+// the variable assigned by the line, if it's an assignment
+pub(crate) fn assignment_key(line: &str) -> Option<&str> {
+    split_assignment(line).map(|(key, _)| key)
+}
+
+fn split_assignment(line: &str) -> Option<(&str, &str)> {
+    let mut line = line.trim_start();
+    if let Some(rest) = line.strip_prefix("export") {
+        if rest.starts_with(char::is_whitespace) {
+            line = rest.trim_start();
+        }
+    }
+    let (key, value) = line.split_once('=')?;
+    if !is_valid_key(key) {
+        return None;
+    }
+    Some((key, value))
+}
+
+fn is_valid_key(key: &str) -> bool {
+    match key.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-'),
+        _ => false,
+    }
+}
+
+fn is_var_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// the word ends at the first unquoted blank, everything after it is ignored
+fn parse_word(key: &str, s: &str, lookup: Option<Lookup>) -> Result<Option<String>> {
+    Ok(parse_word_at(key, s, lookup)?.map(|(word, _)| word))
+}
+
+// the word at the beginning of `s`, with what follows it
+fn parse_word_at<'a>(
+    key: &str,
+    s: &'a str,
+    lookup: Option<Lookup>,
+) -> Result<Option<(String, &'a str)>> {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    let mut in_double_quotes = false;
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() && !in_double_quotes => {
+                return Ok(Some((result, chars.as_str())))
+            }
+            '"' => in_double_quotes = !in_double_quotes,
+            '\\' => match chars.next() {
+                // inside double quotes, backslash escapes only $ ` " and \
+                Some(c) if in_double_quotes && !matches!(c, '$' | '`' | '"' | '\\') => {
+                    result.push('\\');
+                    result.push(c);
+                }
+                Some(c) => result.push(c),
+                None if in_double_quotes => return Ok(None),
+                None => break,
+            },
+            '\'' if !in_double_quotes => loop {
+                match chars.next() {
+                    Some('\'') => break,
+                    Some(c) => result.push(c),
+                    None => return Ok(None),
+                }
+            },
+            '$' if lookup.is_some() => {
+                let name = match chars.clone().next() {
+                    Some('{') => {
+                        chars.next();
+                        let mut name = String::new();
+                        loop {
+                            match chars.next() {
+                                Some('}') => break,
+                                Some(c) if is_var_char(c) => name.push(c),
+                                _ => return Err(PotError::VarExpansionError(key.to_string())),
+                            }
+                        }
+                        name
+                    }
+                    Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                        let mut name = String::new();
+                        while let Some(c) = chars.clone().next().filter(|c| is_var_char(*c)) {
+                            name.push(c);
+                            chars.next();
+                        }
+                        name
+                    }
+                    _ => {
+                        result.push('$');
+                        continue;
+                    }
+                };
+                if name.is_empty() {
+                    return Err(PotError::VarExpansionError(key.to_string()));
+                }
+                match lookup.and_then(|lookup| lookup(&name)) {
+                    Some(value) => result.push_str(&value),
+                    None if name == key => return Err(PotError::SelfReferenceError(name)),
+                    None => (),
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    if in_double_quotes {
+        return Ok(None);
+    }
+    Ok(Some((result, "")))
+}
+
+// distinguishes the temporary files of the writers of the same process
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// write a file atomically: the content is written in a temporary file
+// in the same directory, that is renamed over the destination
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| crate::error::PotError::PathError(format!("{}", path.display())))?;
+    let tmp_path = dir.join(format!(
+        ".{}.tmp.{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = (|| -> Result<()> {
+        let mut tmp_file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            tmp_file.set_permissions(metadata.permissions())?;
+        }
+        tmp_file.write_all(content)?;
+        tmp_file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+// the content of a file, None if it doesn't exist
+pub(crate) fn read_optional(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// create and lock the lock file at `path`; the lock is held until the file is dropped
+pub(crate) fn lock_exclusive(path: &Path) -> Result<File> {
+    let lock = File::create(path)?;
+    lock.lock_exclusive()?;
+    Ok(lock)
+}
+
+// the current time, in seconds since the epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// parse a database file with a record per line, made of fields separated by blanks;
+// empty lines and comments are skipped, a line not understood by `parse` is an `error`,
+// with the path and the line number
+pub(crate) fn parse_records<T>(
+    path: &Path,
+    s: &str,
+    parse: impl Fn(&[&str]) -> Option<T>,
+    error: fn(String) -> PotError,
+) -> Result<Vec<T>> {
+    let mut result = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let record =
+            parse(&fields).ok_or_else(|| error(format!("{}:{}", path.display(), i + 1)))?;
+        result.push(record);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assignment(key: &str, value: &str) -> Option<(String, String)> {
+        Some((key.to_string(), value.to_string()))
+    }
+
+    #[test]
+    fn parse_assignment_001() {
+        assert_eq!(parse_assignment(""), None);
+        assert_eq!(parse_assignment("# FOO=bar"), None);
+        assert_eq!(parse_assignment("FOO"), None);
+        assert_eq!(parse_assignment("=bar"), None);
+        assert_eq!(parse_assignment("1FOO=bar"), None);
+        assert_eq!(parse_assignment("FOO BAR=bar"), None);
+    }
+
+    #[test]
+    fn parse_assignment_002() {
+        assert_eq!(parse_assignment("FOO=bar"), assignment("FOO", "bar"));
+        assert_eq!(parse_assignment("  FOO=bar  "), assignment("FOO", "bar"));
+        assert_eq!(parse_assignment("FOO="), assignment("FOO", ""));
+        assert_eq!(parse_assignment("FOO=a=b"), assignment("FOO", "a=b"));
+        assert_eq!(parse_assignment("FOO=a#b"), assignment("FOO", "a#b"));
+        assert_eq!(parse_assignment("export FOO=bar"), assignment("FOO", "bar"));
+        assert_eq!(
+            parse_assignment("exportFOO=bar"),
+            assignment("exportFOO", "bar")
+        );
+    }
+
+    #[test]
+    fn parse_assignment_003() {
+        assert_eq!(
+            parse_assignment("FOO=bar # comment"),
+            assignment("FOO", "bar")
+        );
+        assert_eq!(
+            parse_assignment("FOO=bar\t# comment"),
+            assignment("FOO", "bar")
+        );
+        assert_eq!(parse_assignment("FOO=bar baz"), assignment("FOO", "bar"));
+    }
+
+    #[test]
+    fn parse_assignment_004() {
+        assert_eq!(parse_assignment("FOO=\"bar\""), assignment("FOO", "bar"));
+        assert_eq!(
+            parse_assignment("FOO=\"a b # c\""),
+            assignment("FOO", "a b # c")
+        );
+        assert_eq!(
+            parse_assignment("FOO='a \\b $c'"),
+            assignment("FOO", "a \\b $c")
+        );
+        assert_eq!(
+            parse_assignment("FOO=\"a \\\"b\\\" \\c\""),
+            assignment("FOO", "a \"b\" \\c")
+        );
+        assert_eq!(parse_assignment("FOO=a\\ b"), assignment("FOO", "a b"));
+        assert_eq!(
+            parse_assignment("FOO=pre'a b'\"c d\"post"),
+            assignment("FOO", "prea bc dpost")
+        );
+    }
+
+    #[test]
+    fn write_atomic_001() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let path = &path;
+                s.spawn(move || write_atomic(path, format!("{}\n", i).as_bytes()).unwrap());
+            }
+        });
+        assert!(std::fs::read_to_string(&path).unwrap().ends_with('\n'));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn parse_assignment_005() {
+        assert_eq!(parse_assignment("FOO=\"bar"), None);
+        assert_eq!(parse_assignment("FOO='bar"), None);
+        assert_eq!(parse_assignment("FOO=\"bar\\"), None);
+    }
+
+    #[test]
+    fn parse_assignment_words_001() {
+        assert_eq!(
+            parse_assignment_words("pot.cmd=sh /etc/rc"),
+            assignment("pot.cmd", "sh /etc/rc")
+        );
+        assert_eq!(
+            parse_assignment_words("ip=\"10.192.0.3\"  fd00::3 # static"),
+            assignment("ip", "10.192.0.3 fd00::3")
+        );
+        assert_eq!(
+            parse_assignment_words("host.hostname='web pot'"),
+            assignment("host.hostname", "web pot")
+        );
+        assert_eq!(parse_assignment_words("ip="), assignment("ip", ""));
+        assert_eq!(parse_assignment_words("ip=a#b"), assignment("ip", "a#b"));
+        assert_eq!(parse_assignment_words("ip=\"a"), None);
+        assert_eq!(parse_assignment_words("# ip=a"), None);
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "POT_ROOT" => Some("/opt/pot".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parse_assignment_expanded_001() {
+        let uut = parse_assignment_expanded("FOO=${POT_ROOT}/fs", &lookup).unwrap();
+        assert_eq!(uut, assignment("FOO", "/opt/pot/fs"));
+        let uut = parse_assignment_expanded("FOO=$POT_ROOT/fs", &lookup).unwrap();
+        assert_eq!(uut, assignment("FOO", "/opt/pot/fs"));
+        let uut = parse_assignment_expanded("FOO=\"$POT_ROOT\"x$EMPTY", &lookup).unwrap();
+        assert_eq!(uut, assignment("FOO", "/opt/potx"));
+        let uut = parse_assignment_expanded("FOO=$UNDEFINED/fs", &lookup).unwrap();
+        assert_eq!(uut, assignment("FOO", "/fs"));
+    }
+
+    #[test]
+    fn parse_assignment_expanded_002() {
+        let uut = parse_assignment_expanded("FOO='$POT_ROOT'", &lookup).unwrap();
+        assert_eq!(uut, assignment("FOO", "$POT_ROOT"));
+        let uut = parse_assignment_expanded("FOO=\\$POT_ROOT", &lookup).unwrap();
+        assert_eq!(uut, assignment("FOO", "$POT_ROOT"));
+        let uut = parse_assignment_expanded("FOO=a$ $1", &lookup).unwrap();
+        assert_eq!(uut, assignment("FOO", "a$"));
+        let uut = parse_assignment_expanded("# FOO=$BAR", &lookup).unwrap();
+        assert_eq!(uut, None);
+    }
+
+    #[test]
+    fn parse_assignment_expanded_003() {
+        assert!(parse_assignment_expanded("FOO=${POT_ROOT", &lookup).is_err());
+        assert!(parse_assignment_expanded("FOO=${}", &lookup).is_err());
+        assert!(parse_assignment_expanded("FOO=${POT_ROOT:-/}", &lookup).is_err());
+        assert!(parse_assignment_expanded("FOO=${FOO}/bar", &lookup).is_err());
+        let uut = parse_assignment_expanded("POT_ROOT=${POT_ROOT}/bar", &lookup).unwrap();
+        assert_eq!(uut, assignment("POT_ROOT", "/opt/pot/bar"));
     }
 }
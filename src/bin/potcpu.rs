@@ -1,9 +1,10 @@
 use anyhow::{anyhow, Result};
 use itertools::Itertools;
 use log::{info, trace, warn};
+use pot::runner::{CommandRunner, SystemRunner};
 use pot::{get_running_pot_list, PotSystemConfig};
 use std::collections::HashMap;
-use std::process::{Command as PCommand, Stdio};
+use std::path::PathBuf;
 use structopt::StructOpt;
 use structopt_flags::{LogLevel, QuietVerbose};
 
@@ -12,6 +13,17 @@ use structopt_flags::{LogLevel, QuietVerbose};
 struct Opt {
     #[structopt(flatten)]
     verbose: QuietVerbose,
+    /// The pot installation prefix [default: POT_PREFIX or where pot is found in PATH]
+    #[structopt(long = "--prefix", parse(from_os_str), global = true)]
+    prefix: Option<PathBuf>,
+    /// The pot.conf file, with pot.default.conf in the same directory [default: POT_CONF]
+    #[structopt(
+        long = "--config",
+        parse(from_os_str),
+        global = true,
+        conflicts_with = "prefix"
+    )]
+    config: Option<PathBuf>,
     #[structopt(subcommand)]
     subcommand: Command,
 }
@@ -52,9 +64,7 @@ fn allocation_from_utf8(v: &[u8]) -> Result<Allocation> {
     let result: Vec<u32> = mask
         .split(',')
         .map(str::trim)
-        .map(str::parse)
-        .filter(std::result::Result::is_ok)
-        .map(std::result::Result::unwrap)
+        .flat_map(str::parse)
         .collect();
     Ok(result)
 }
@@ -72,32 +82,22 @@ fn allocation_to_string(allocation: &AllocationRef, ncpu: u32) -> String {
     }
 }
 
-fn get_ncpu() -> Result<u32> {
-    let output = PCommand::new("/sbin/sysctl")
-        .arg("-n")
-        .arg("hw.ncpu")
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()?;
+fn get_ncpu(runner: &dyn CommandRunner) -> Result<u32> {
+    let output = runner.run("/sbin/sysctl", &["-n", "hw.ncpu"])?;
 
     let output_string = std::str::from_utf8(&output.stdout)?;
     let ncpu: u32 = output_string.trim().parse()?;
     Ok(ncpu)
 }
 
-fn get_cpusets(conf: &PotSystemConfig) -> Result<HashMap<String, Allocation>> {
+fn get_cpusets(
+    conf: &PotSystemConfig,
+    runner: &dyn CommandRunner,
+) -> Result<HashMap<String, Allocation>> {
     let mut result = HashMap::new();
-    for pot in get_running_pot_list(conf) {
-        let output = PCommand::new("/usr/bin/cpuset")
-            .arg("-g")
-            .arg("-j")
-            .arg(&pot)
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .output()?;
-        if !output.status.success() {
+    for pot in get_running_pot_list(conf, runner) {
+        let output = runner.run("/usr/bin/cpuset", &["-g", "-j", &pot])?;
+        if !output.success() {
             warn!("failed to get cpuset information for pot {}", pot);
             continue;
         }
@@ -109,9 +109,10 @@ fn get_cpusets(conf: &PotSystemConfig) -> Result<HashMap<String, Allocation>> {
 
 fn get_potcpuconstraints(
     allocations: &HashMap<String, Allocation>,
+    runner: &dyn CommandRunner,
 ) -> Result<HashMap<String, u32>> {
     let mut result = HashMap::new();
-    let ncpu = get_ncpu()?;
+    let ncpu = get_ncpu(runner)?;
     for (pot_name, allocation) in allocations {
         if allocation.len() as u32 == ncpu {
             continue;
@@ -121,10 +122,10 @@ fn get_potcpuconstraints(
     Ok(result)
 }
 
-fn show(opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
-    let ncpu = get_ncpu()?;
-    let pot_cpusets = get_cpusets(conf)?;
-    let pot_constraints = get_potcpuconstraints(&pot_cpusets)?;
+fn show(opt: &Opt, conf: &PotSystemConfig, runner: &dyn CommandRunner) -> Result<()> {
+    let ncpu = get_ncpu(runner)?;
+    let pot_cpusets = get_cpusets(conf, runner)?;
+    let pot_constraints = get_potcpuconstraints(&pot_cpusets, runner)?;
     for (pot_name, allocation) in pot_cpusets {
         let constraint_string = match pot_constraints.iter().find(|(name, _)| *name == &pot_name) {
             Some(constraint) => constraint.1.to_string(),
@@ -135,7 +136,7 @@ fn show(opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
         println!("\tCPU used: {}", allocation_to_string(&allocation, ncpu));
     }
     if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
-        let cpu_allocations = get_cpu_allocation(conf)?;
+        let cpu_allocations = get_cpu_allocation(conf, runner)?;
         for (cpu, pots) in cpu_allocations
             .into_iter()
             .sorted_by_key(|(cpu, _pots)| *cpu)
@@ -146,9 +147,12 @@ fn show(opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
     Ok(())
 }
 
-fn get_cpu_allocation(conf: &PotSystemConfig) -> Result<HashMap<u32, u32>> {
-    let pot_cpusets = get_cpusets(conf)?;
-    let ncpu = get_ncpu()?;
+fn get_cpu_allocation(
+    conf: &PotSystemConfig,
+    runner: &dyn CommandRunner,
+) -> Result<HashMap<u32, u32>> {
+    let pot_cpusets = get_cpusets(conf, runner)?;
+    let ncpu = get_ncpu(runner)?;
     let mut result: HashMap<u32, u32> = HashMap::new();
     for i in 0..ncpu {
         result.insert(i, 0);
@@ -162,13 +166,18 @@ fn get_cpu_allocation(conf: &PotSystemConfig) -> Result<HashMap<u32, u32>> {
     Ok(result)
 }
 
-fn get_cpu(_opt: &Opt, conf: &PotSystemConfig, cpu_amount: u32) -> Result<()> {
-    let ncpu = get_ncpu()?;
+fn get_cpu(
+    _opt: &Opt,
+    conf: &PotSystemConfig,
+    runner: &dyn CommandRunner,
+    cpu_amount: u32,
+) -> Result<()> {
+    let ncpu = get_ncpu(runner)?;
     if ncpu <= cpu_amount {
         info!("Not enough CPU in the system to provide a meaningful allocation");
         return Ok(());
     }
-    let cpu_allocations = get_cpu_allocation(conf)?;
+    let cpu_allocations = get_cpu_allocation(conf, runner)?;
     let sorted_cpu_allocations = cpu_allocations
         .iter()
         .sorted_by_key(|(cpu, _allocations)| *cpu)
@@ -182,8 +191,8 @@ fn get_cpu(_opt: &Opt, conf: &PotSystemConfig, cpu_amount: u32) -> Result<()> {
     Ok(())
 }
 
-fn rebalance(_opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
-    let cpu_counters = get_cpu_allocation(conf)?;
+fn rebalance(_opt: &Opt, conf: &PotSystemConfig, runner: &dyn CommandRunner) -> Result<()> {
+    let cpu_counters = get_cpu_allocation(conf, runner)?;
     let min = cpu_counters
         .iter()
         .min_by_key(|(_cpu, allocation)| *allocation)
@@ -198,12 +207,12 @@ fn rebalance(_opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
     } else {
         info!("rebalance needed : min {} max {}", min.1, max.1);
     }
-    let ncpu = get_ncpu()?;
-    let pot_allocations = get_cpusets(conf)?;
-    let pot_constraints = get_potcpuconstraints(&pot_allocations)?;
+    let ncpu = get_ncpu(runner)?;
+    let pot_allocations = get_cpusets(conf, runner)?;
+    let pot_constraints = get_potcpuconstraints(&pot_allocations, runner)?;
     let mut pot_new_allocations = HashMap::new();
     let mut cpu_index_counter: u32 = 0;
-    for (pot_name, amount_cpu) in pot_constraints.iter().sorted_by(|a, b| a.0.cmp(&b.0)) {
+    for (pot_name, amount_cpu) in pot_constraints.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
         let mut cpus: Vec<u32> = Vec::new();
         for _ in 0..*amount_cpu {
             cpus.push(cpu_index_counter);
@@ -226,16 +235,125 @@ fn rebalance(_opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
     }
     Ok(())
 }
+fn load_conf(opt: &Opt, runner: &dyn CommandRunner) -> Result<PotSystemConfig> {
+    let conf = if let Some(config) = &opt.config {
+        PotSystemConfig::from_conf(config)?
+    } else if let Some(prefix) = &opt.prefix {
+        PotSystemConfig::from_prefix(prefix)?
+    } else {
+        PotSystemConfig::from_system_with_runner(runner)?
+    };
+    if let Some(source) = &conf.source {
+        info!("configuration read from {}", source);
+    }
+    Ok(conf)
+}
+
 fn main() -> Result<()> {
     let opt = Opt::from_args();
     opt.verbose.set_log_level();
     trace!("potcpu start");
 
-    let conf = PotSystemConfig::from_system()?;
+    let runner = SystemRunner;
+    let conf = load_conf(&opt, &runner)?;
     match opt.subcommand {
-        Command::Show => show(&opt, &conf)?,
-        Command::GetCpu(cmd_opt) => get_cpu(&opt, &conf, cmd_opt.cpu_amount)?,
-        Command::Rebalance => rebalance(&opt, &conf)?,
+        Command::Show => show(&opt, &conf, &runner)?,
+        Command::GetCpu(cmd_opt) => get_cpu(&opt, &conf, &runner, cmd_opt.cpu_amount)?,
+        Command::Rebalance => rebalance(&opt, &conf, &runner)?,
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pot::runner::ScriptedRunner;
+
+    fn test_conf(fs_root: &std::path::Path) -> PotSystemConfig {
+        PotSystemConfig {
+            zfs_root: "zroot/pot".to_string(),
+            fs_root: fs_root.to_str().unwrap().to_string(),
+            network: "10.192.0.0/10".parse().unwrap(),
+            netmask: "255.192.0.0".parse().unwrap(),
+            gateway: "10.192.0.1".parse().unwrap(),
+            ext_if: "em0".to_string(),
+            dns_name: "dns".to_string(),
+            dns_ip: "10.192.0.2".parse().unwrap(),
+            network6: None,
+            gateway6: None,
+            dns_ip6: None,
+            reserved: Vec::new(),
+            ip_allocation: pot::allocation::Strategy::Lowest,
+            ip_quarantine: 0,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn get_ncpu_001() {
+        let runner =
+            ScriptedRunner::new().with_output("/sbin/sysctl", &["-n", "hw.ncpu"], 0, "8\n");
+        let uut = get_ncpu(&runner);
+        assert_eq!(uut.unwrap(), 8);
+    }
+
+    #[test]
+    fn get_ncpu_002() {
+        let runner = ScriptedRunner::new().with_output("/sbin/sysctl", &["-n", "hw.ncpu"], 0, "");
+        assert!(get_ncpu(&runner).is_err());
+        assert!(get_ncpu(&ScriptedRunner::new()).is_err());
+    }
+
+    #[test]
+    fn get_cpusets_001() {
+        let fs_root = tempfile::tempdir().unwrap();
+        for pot in &["foo", "bar", "baz"] {
+            std::fs::create_dir_all(fs_root.path().join("jails").join(pot)).unwrap();
+        }
+        let conf = test_conf(fs_root.path());
+        let runner = ScriptedRunner::new()
+            .with_output("/usr/sbin/jls", &["-j", "foo"], 0, "")
+            .with_output("/usr/sbin/jls", &["-j", "bar"], 0, "")
+            .with_output("/usr/sbin/jls", &["-j", "baz"], 1, "")
+            .with_output(
+                "/usr/bin/cpuset",
+                &["-g", "-j", "foo"],
+                0,
+                "jail 3 mask: 0, 1\njail 3 domain policy: first-touch mask: 0\n",
+            )
+            .with_output("/usr/bin/cpuset", &["-g", "-j", "bar"], 1, "");
+        let uut = get_cpusets(&conf, &runner).unwrap();
+        assert_eq!(uut.len(), 1);
+        assert_eq!(uut.get("foo"), Some(&vec![0, 1]));
+    }
+
+    #[test]
+    fn get_cpu_allocation_001() {
+        let fs_root = tempfile::tempdir().unwrap();
+        for pot in &["foo", "bar"] {
+            std::fs::create_dir_all(fs_root.path().join("jails").join(pot)).unwrap();
+        }
+        let conf = test_conf(fs_root.path());
+        let runner = ScriptedRunner::new()
+            .with_output("/sbin/sysctl", &["-n", "hw.ncpu"], 0, "4\n")
+            .with_output("/usr/sbin/jls", &["-j", "foo"], 0, "")
+            .with_output("/usr/sbin/jls", &["-j", "bar"], 0, "")
+            .with_output(
+                "/usr/bin/cpuset",
+                &["-g", "-j", "foo"],
+                0,
+                "jail 1 mask: 0, 1\n",
+            )
+            .with_output(
+                "/usr/bin/cpuset",
+                &["-g", "-j", "bar"],
+                0,
+                "jail 2 mask: 1\n",
+            );
+        let uut = get_cpu_allocation(&conf, &runner).unwrap();
+        assert_eq!(uut.get(&0), Some(&1));
+        assert_eq!(uut.get(&1), Some(&2));
+        assert_eq!(uut.get(&2), Some(&0));
+        assert_eq!(uut.get(&3), Some(&0));
+    }
+}
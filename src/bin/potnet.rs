@@ -1,19 +1,55 @@
 use anyhow::{bail, Result};
 use ipnet::IpNet;
-use log::{debug, error, info, trace};
-use pot::bridge::{get_bridges_list, BridgeConf};
-use pot::{get_pot_conf_list, NetType, PotSystemConfig};
+use log::{debug, error, info, trace, warn};
+use pot::allocation::{save_last_allocation, Strategy};
+use pot::bridge::{
+    check_bridges, create_bridge, create_bridges, delete_bridge, get_bridge, get_bridge_members,
+    get_bridge_path, get_bridges_list, resize_bridge, BridgeConf,
+};
+use pot::confdoc::shell_quote;
+use pot::diagnostic::Severity;
+use pot::error::PotError;
+use pot::ipam::{get_prefix_length, Fit, FreeSpace, IpDb, Owner};
+use pot::iprange::{new_net, IpRange};
+use pot::lease::LeaseDb;
+use pot::potconf::IpStack;
+use pot::quarantine::QuarantineDb;
+use pot::reservation::get_reservations;
+use pot::{get_pot_conf_list, get_pot_conf_reports, NetType, PotConfReport, PotSystemConfig};
+use serde::{Serialize, Serializer};
 use std::collections::BTreeMap;
 use std::net::IpAddr;
-use std::net::IpAddr::{V4, V6};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::string::String;
+use std::time::Duration;
 use structopt::StructOpt;
 use structopt_flags::{HostParam, LogLevel};
+use thiserror::Error;
 
 #[derive(Clone, Debug, StructOpt)]
 struct Opt {
     #[structopt(flatten)]
     verbose: structopt_flags::QuietVerbose,
+    /// The pot installation prefix [default: POT_PREFIX or where pot is found in PATH]
+    #[structopt(long = "--prefix", parse(from_os_str), global = true)]
+    prefix: Option<PathBuf>,
+    /// The pot.conf file, with pot.default.conf in the same directory [default: POT_CONF]
+    #[structopt(
+        long = "--config",
+        parse(from_os_str),
+        global = true,
+        conflicts_with = "prefix"
+    )]
+    config: Option<PathBuf>,
+    /// The output format; with json and shell, errors are reported on stdout too
+    #[structopt(
+        long = "--output",
+        global = true,
+        default_value = "text",
+        possible_values = &["text", "json", "shell"]
+    )]
+    output: OutputFormat,
     #[structopt(subcommand)]
     subcommand: Command,
 }
@@ -25,8 +61,11 @@ enum Command {
     Show(BridgeOpt),
     /// Provides the next available IP address
     #[structopt(name = "next")]
-    Next(BridgeOpt),
-    /// Check the POT config
+    Next(NextOpt),
+    /// Release the addresses reserved via next --reserve
+    #[structopt(name = "release")]
+    Release(ReleaseOpt),
+    /// Check the POT config; the exit status is 1 if errors are found, 2 if only warnings are
     #[structopt(name = "config-check")]
     ConfigCheck,
     /// Validate the IP address provided as parameter
@@ -41,12 +80,60 @@ enum Command {
     /// Check if the argument is a valid ip address
     #[structopt(name = "ipcheck")]
     IP(CheckOpt),
-    /// Provide the next available network
+    /// Provide the next available network, in the bridge file format
     #[structopt(name = "new-net")]
     NewNetwork(NewNetOpt),
     /// Generate the etc/hosts file with all know hosts in the specific bridge
     #[structopt(name = "etc-hosts")]
     EtcHosts(BridgeOpt),
+    /// List the addresses claimed by more than one pot, bridge or system address
+    #[structopt(name = "duplicates")]
+    Duplicates,
+    /// Manage the private bridges
+    #[structopt(name = "bridge")]
+    Bridge(BridgeCommand),
+    /// Pack the networks of several new bridges in the free space of the pot network
+    #[structopt(name = "plan-nets")]
+    PlanNets(PlanOpt),
+    /// Show the free addresses of the pot network or of a bridge, as ranges and networks
+    #[structopt(name = "free")]
+    Free(FreeOpt),
+}
+
+#[derive(Clone, Debug, StructOpt)]
+enum BridgeCommand {
+    /// Create a bridge, in the first free subnet of the pot network big enough or in the given one
+    #[structopt(name = "create")]
+    Create(BridgeSizeOpt),
+    /// Delete a bridge not used by any pot
+    #[structopt(name = "delete")]
+    Delete(BridgeNameOpt),
+    /// Change the network of a bridge, that still has to contain all its pots
+    #[structopt(name = "resize")]
+    Resize(BridgeSizeOpt),
+    /// List the bridges and their pots
+    #[structopt(name = "list")]
+    List,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct BridgeNameOpt {
+    /// The name of the private bridge
+    #[structopt(short = "-b", long = "--bridge-name")]
+    bridge_name: String,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct BridgeSizeOpt {
+    /// The name of the private bridge
+    #[structopt(short = "-b", long = "--bridge-name")]
+    bridge_name: String,
+    /// The number of host to be included in the network (gateway excluded)
+    #[structopt(short = "-s", required_unless = "net", conflicts_with = "net")]
+    host_number: Option<u128>,
+    /// The network of the bridge
+    #[structopt(long = "--net")]
+    net: Option<IpNet>,
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -56,6 +143,57 @@ struct BridgeOpt {
     bridge_name: Option<String>,
 }
 
+#[derive(Clone, Debug, StructOpt)]
+struct NextOpt {
+    /// The name of a private bridge
+    #[structopt(short = "-b", long = "--bridge-name")]
+    bridge_name: Option<String>,
+    /// Reserve the address, until its pot is created or the reservation expires
+    #[structopt(short = "-r", long = "--reserve", requires = "owner")]
+    reserve: bool,
+    /// The name of the pot the address is reserved for
+    #[structopt(short = "-o", long = "--owner", requires = "reserve")]
+    owner: Option<String>,
+    /// The duration of the reservation, in seconds
+    #[structopt(long = "--ttl", default_value = "600")]
+    ttl: u64,
+    /// The pot the address is for: its static address is provided, if reserved
+    #[structopt(long = "--for")]
+    for_pot: Option<String>,
+    /// How the free address is chosen [default: POT_IP_ALLOCATION or lowest]
+    #[structopt(
+        long = "--strategy",
+        possible_values = &["lowest", "highest", "round-robin", "random", "hash"]
+    )]
+    strategy: Option<Strategy>,
+    /// The address family: 4, 6 or both, for a pair [default: the family of POT_NETWORK]
+    #[structopt(
+        long = "--family",
+        parse(try_from_str = parse_family),
+        conflicts_with = "bridge-name"
+    )]
+    family: Option<IpStack>,
+}
+
+fn parse_family(s: &str) -> std::result::Result<IpStack, String> {
+    match s {
+        "4" => Ok(IpStack::Ipv4),
+        "6" => Ok(IpStack::Ipv6),
+        "both" => Ok(IpStack::Dual),
+        _ => Err(format!("invalid family {}, use 4, 6 or both", s)),
+    }
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct ReleaseOpt {
+    /// The reserved address to release
+    #[structopt(short = "-H", long = "--host", required_unless = "owner")]
+    ip: Option<IpAddr>,
+    /// Release all the addresses reserved for this pot
+    #[structopt(short = "-o", long = "--owner", conflicts_with = "ip")]
+    owner: Option<String>,
+}
+
 #[derive(Clone, Debug, StructOpt)]
 struct ValidateOpt {
     #[structopt(flatten)]
@@ -63,6 +201,9 @@ struct ValidateOpt {
     /// The name of the private bridge, if the IP belongs to it
     #[structopt(short = "-b", long = "--bridge-name")]
     bridge_name: Option<String>,
+    /// The pot the address is for, allowed to use its static address
+    #[structopt(long = "--for")]
+    for_pot: Option<String>,
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -74,310 +215,1497 @@ struct CheckOpt {
 #[derive(Clone, Debug, StructOpt)]
 struct NewNetOpt {
     /// The number of host to be included in the network (gateway excluded)
-    #[structopt(short = "-s")]
-    host_number: u16,
+    #[structopt(
+        short = "-s",
+        required_unless = "prefix-len",
+        conflicts_with = "prefix-len"
+    )]
+    host_number: Option<u128>,
+    /// The prefix length of the network
+    #[structopt(long = "--prefix-len")]
+    prefix_len: Option<u8>,
+    /// The network the subnet is carved from, inside POT_NETWORK or POT_NETWORK6
+    /// [default: the network of the family]
+    #[structopt(long = "--within")]
+    within: Option<IpNet>,
+    /// The address family, 4 or 6 [default: the family of POT_NETWORK]
+    #[structopt(
+        long = "--family",
+        parse(try_from_str = parse_family),
+        conflicts_with = "within"
+    )]
+    family: Option<IpStack>,
+    /// How the subnet is chosen: the first free one, or the one in the smallest free block
+    #[structopt(
+        long = "--fit",
+        default_value = "first",
+        possible_values = &["first", "best"]
+    )]
+    fit: Fit,
+    /// The name of the bridge, added to the output
+    #[structopt(short = "-b", long = "--bridge-name")]
+    bridge_name: Option<String>,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct PlanOpt {
+    /// The bridges, as comma separated <name>=<number of hosts>, gateway excluded
+    #[structopt(long = "--sizes")]
+    sizes: BridgeSizes,
+    /// Create the bridges of the plan
+    #[structopt(long = "--apply")]
+    apply: bool,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct FreeOpt {
+    /// The name of a private bridge
+    #[structopt(short = "-b", long = "--bridge-name")]
+    bridge_name: Option<String>,
+    /// The address family of the pot network, 4 or 6 [default: the family of POT_NETWORK]
+    #[structopt(
+        long = "--family",
+        parse(try_from_str = parse_family),
+        conflicts_with = "bridge-name"
+    )]
+    family: Option<IpStack>,
 }
 
-fn show(opt: &Opt, conf: &PotSystemConfig, ip_db: &mut BTreeMap<IpAddr, Option<String>>) {
-    println!("Network topology:");
-    println!("\tnetwork : {}", conf.network.trunc());
-    println!("\tmin addr: {}", conf.network.network());
-    println!("\tmax addr: {}", conf.network.broadcast());
-    println!("\nAddresses already taken:");
-    for (ip, opt_name) in ip_db.iter() {
-        println!("\t{}\t{}", ip, opt_name.as_ref().unwrap_or(&"".to_string()));
+/// The number of hosts of each bridge, in the order given
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BridgeSizes(Vec<(String, u128)>);
+
+impl FromStr for BridgeSizes {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut result: Vec<(String, u128)> = Vec::new();
+        for item in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (name, size) = item
+                .split_once('=')
+                .ok_or_else(|| format!("{} is not <name>=<number of hosts>", item))?;
+            let name = name.trim();
+            let size = size
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid number of hosts {} for {}", size, name))?;
+            if result.iter().any(|(n, _)| n == name) {
+                return Err(format!("bridge {} given more than once", name));
+            }
+            result.push((name.to_string(), size));
+        }
+        if result.is_empty() {
+            return Err("no bridge given".to_string());
+        }
+        Ok(BridgeSizes(result))
     }
-    if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
-        println!("\nDebug information\n{:#?}", conf);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Shell,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "shell" => Ok(OutputFormat::Shell),
+            _ => Err(format!("unknown output format {}", s)),
+        }
     }
 }
 
-fn show_bridge(_opt: &Opt, conf: &PotSystemConfig, bridge_name: &str) -> Result<()> {
-    let bridges_list = get_bridges_list(conf)?;
-    if let Some(bridge) = bridges_list.iter().find(|x| x.name == bridge_name) {
-        info!("bridge {} found", bridge.name);
-        let mut ip_db = BTreeMap::new();
-        init_bridge_ipdb(&bridge, conf, &mut ip_db);
-        for (ip, opt_name) in ip_db.iter() {
-            println!(
-                "\t{}\t{}",
-                ip,
-                match opt_name {
-                    Some(s) => s,
-                    None => "",
-                }
-            );
+/// The errors of potnet; the code is part of the machine-readable output and has to be stable
+#[derive(Debug, Error)]
+enum PotnetError {
+    #[error("The {0} output is not supported by this command")]
+    UnsupportedOutput(&'static str),
+}
+
+impl PotnetError {
+    fn code(&self) -> &'static str {
+        match self {
+            PotnetError::UnsupportedOutput(_) => "unsupported-output",
         }
+    }
+}
+
+fn error_code(e: &anyhow::Error) -> &'static str {
+    if let Some(e) = e.downcast_ref::<PotnetError>() {
+        e.code()
+    } else if let Some(e) = e.downcast_ref::<PotError>() {
+        e.code()
+    } else if e.downcast_ref::<std::io::Error>().is_some() {
+        "io-error"
     } else {
-        error!("bridge {} not found", bridge_name);
+        "internal-error"
+    }
+}
+
+fn serialize_display<T: std::fmt::Display, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn serialize_display_opt<T: std::fmt::Display, S: Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.collect_str(value),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// The result of a command, that can be printed in every output format
+trait Report: Serialize {
+    fn text(&self, verbose: bool) -> String;
+    /// The variables to be evaluated by a shell, None if the shell output is not supported
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        None
+    }
+}
+
+fn join<T: std::fmt::Display>(values: impl Iterator<Item = T>) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+fn is_verbose(opt: &Opt) -> bool {
+    opt.verbose.get_level_filter() > log::LevelFilter::Warn
+}
+
+fn print_report<R: Report>(opt: &Opt, report: &R) -> Result<()> {
+    match opt.output {
+        OutputFormat::Text => print!("{}", report.text(is_verbose(opt))),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(report)?),
+        OutputFormat::Shell => match report.shell() {
+            Some(vars) => {
+                for (name, value) in vars {
+                    println!("{}={}", name, shell_quote(&value));
+                }
+            }
+            None => bail!(PotnetError::UnsupportedOutput("shell")),
+        },
     }
     Ok(())
 }
 
-fn get(opt: &Opt, conf: &PotSystemConfig, ip_db: &BTreeMap<IpAddr, Option<String>>) {
-    for addr in conf.network.hosts() {
-        if !ip_db.contains_key(&addr) {
-            if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
-                println!("{} available", addr);
-            } else {
-                println!("{}", addr);
+fn print_error(opt: &Opt, e: &anyhow::Error) {
+    match opt.output {
+        OutputFormat::Text => eprintln!("Error: {:?}", e),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::json!({
+                "error": {
+                    "code": error_code(e),
+                    "message": format!("{:#}", e),
+                }
+            })
+        ),
+        OutputFormat::Shell => {
+            println!("error={}", error_code(e));
+            println!("error_message={}", shell_quote(&format!("{:#}", e)));
+        }
+    }
+}
+
+/// The JSON form of an address owner: its kind, its name and the network type of a pot
+#[derive(Debug, Serialize)]
+struct OwnerEntry {
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_display_opt"
+    )]
+    network_type: Option<NetType>,
+}
+
+impl From<&Owner> for OwnerEntry {
+    fn from(owner: &Owner) -> Self {
+        OwnerEntry {
+            kind: owner.kind(),
+            name: owner.name().map(str::to_string),
+            network_type: match owner {
+                Owner::Pot { network_type, .. } => Some(*network_type),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// A range of taken addresses, a single one if `ip` and `last` are equal
+#[derive(Debug, Serialize)]
+struct AddressEntry {
+    ip: IpAddr,
+    last: IpAddr,
+    owner: OwnerEntry,
+    #[serde(skip)]
+    range: IpRange,
+    #[serde(skip)]
+    description: String,
+}
+
+fn address_entries(ip_db: &IpDb) -> Vec<AddressEntry> {
+    ip_db
+        .iter()
+        .map(|(range, owner)| AddressEntry {
+            ip: range.start,
+            last: range.end,
+            owner: owner.into(),
+            range,
+            description: owner.to_string(),
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+struct Problem {
+    pot: String,
+    #[serde(serialize_with = "serialize_display")]
+    severity: Severity,
+    file: PathBuf,
+    line: Option<usize>,
+    key: Option<String>,
+    reason: String,
+    #[serde(skip)]
+    description: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ShowReport {
+    network: IpNet,
+    min_addr: IpAddr,
+    max_addr: IpAddr,
+    /// The IPv6 pool of a dual-stack system
+    network6: Option<IpNet>,
+    addresses: Vec<AddressEntry>,
+    problems: Vec<Problem>,
+    #[serde(skip)]
+    debug: String,
+}
+
+impl Report for ShowReport {
+    fn text(&self, verbose: bool) -> String {
+        let mut result = String::from("Network topology:\n");
+        result.push_str(&format!("\tnetwork : {}\n", self.network));
+        result.push_str(&format!("\tmin addr: {}\n", self.min_addr));
+        result.push_str(&format!("\tmax addr: {}\n", self.max_addr));
+        if let Some(network6) = self.network6 {
+            result.push_str(&format!("\tnetwork6: {}\n", network6));
+        }
+        result.push_str("\nAddresses already taken:\n");
+        for a in &self.addresses {
+            result.push_str(&format!("\t{}\t{}\n", a.range, a.description));
+        }
+        if !self.problems.is_empty() {
+            result.push_str("\nPot configuration problems:\n");
+            for p in &self.problems {
+                result.push_str(&format!("\t{}\t{}\n", p.pot, p.description));
             }
-            break;
-        } else if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
-            println!("{} already used", addr);
         }
+        if verbose {
+            result.push_str(&format!("\nDebug information\n{}\n", self.debug));
+        }
+        result
     }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![
+            ("network", self.network.to_string()),
+            ("min_addr", self.min_addr.to_string()),
+            ("max_addr", self.max_addr.to_string()),
+            (
+                "network6",
+                self.network6.map(|n| n.to_string()).unwrap_or_default(),
+            ),
+            ("addresses", join(self.addresses.iter().map(|a| a.range))),
+        ])
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BridgeReport {
+    bridge: String,
+    network: IpNet,
+    gateway: IpAddr,
+    network6: Option<IpNet>,
+    gateway6: Option<IpAddr>,
+    mtu: Option<u16>,
+    vlan: Option<u16>,
+    description: Option<String>,
+    isolated: bool,
+    addresses: Vec<AddressEntry>,
 }
 
-fn get_network_size(host_number: u16) -> Option<u8> {
-    if host_number == 0 {
-        return None;
+impl Report for BridgeReport {
+    fn text(&self, _verbose: bool) -> String {
+        let mut result = format!("Bridge {}:\n", self.bridge);
+        if let Some(description) = &self.description {
+            result.push_str(&format!("\tdescription: {}\n", description));
+        }
+        result.push_str(&format!("\tnetwork : {}\n", self.network));
+        result.push_str(&format!("\tgateway : {}\n", self.gateway));
+        if let Some(network6) = self.network6 {
+            result.push_str(&format!("\tnetwork6: {}\n", network6));
+        }
+        if let Some(gateway6) = self.gateway6 {
+            result.push_str(&format!("\tgateway6: {}\n", gateway6));
+        }
+        if let Some(mtu) = self.mtu {
+            result.push_str(&format!("\tmtu     : {}\n", mtu));
+        }
+        if let Some(vlan) = self.vlan {
+            result.push_str(&format!("\tvlan    : {}\n", vlan));
+        }
+        if self.isolated {
+            result.push_str("\tisolated\n");
+        }
+        result.push_str("\nAddresses already taken:\n");
+        for a in &self.addresses {
+            result.push_str(&format!("\t{}\t{}\n", a.range, a.description));
+        }
+        result
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        Some(vec![
+            ("bridge", self.bridge.clone()),
+            ("network", self.network.to_string()),
+            ("gateway", self.gateway.to_string()),
+            ("network6", optional(self.network6.map(|n| n.to_string()))),
+            ("gateway6", optional(self.gateway6.map(|g| g.to_string()))),
+            ("mtu", optional(self.mtu.map(|m| m.to_string()))),
+            ("vlan", optional(self.vlan.map(|v| v.to_string()))),
+            ("description", optional(self.description.clone())),
+            ("isolated", self.isolated.to_string()),
+            ("addresses", join(self.addresses.iter().map(|a| a.range))),
+        ])
     }
-    let mut max_hosts = 4u16;
-    let mut result = 2;
-    loop {
-        if host_number <= max_hosts - 2 {
-            break;
+}
+
+/// A bridge file, as written by potnet bridge
+#[derive(Debug, Serialize)]
+struct BridgeDefinitionReport {
+    name: String,
+    net: IpNet,
+    gateway: IpAddr,
+}
+
+impl From<BridgeConf> for BridgeDefinitionReport {
+    fn from(bridge: BridgeConf) -> Self {
+        BridgeDefinitionReport {
+            name: bridge.name,
+            net: bridge.network,
+            gateway: bridge.gateway,
         }
-        max_hosts <<= 1;
-        result += 1;
     }
-    Some(result)
 }
 
-fn get_prefix_length(host_number: u16, ip_addr: &IpAddr) -> Option<u8> {
-    if let Some(network_size) = get_network_size(host_number) {
+impl Report for BridgeDefinitionReport {
+    fn text(&self, _verbose: bool) -> String {
+        format!(
+            "name={}\nnet={}\ngateway={}\n",
+            self.name, self.net, self.gateway
+        )
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![
+            ("name", self.name.clone()),
+            ("net", self.net.to_string()),
+            ("gateway", self.gateway.to_string()),
+        ])
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BridgeEntry {
+    name: String,
+    net: IpNet,
+    gateway: IpAddr,
+    /// The pots attached to the bridge
+    pots: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BridgesReport {
+    bridges: Vec<BridgeEntry>,
+}
+
+impl Report for BridgesReport {
+    fn text(&self, _verbose: bool) -> String {
+        self.bridges
+            .iter()
+            .map(|b| {
+                format!(
+                    "{}\t{}\t{}\t{}\n",
+                    b.name,
+                    b.net,
+                    b.gateway,
+                    b.pots.join(",")
+                )
+            })
+            .collect()
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![(
+            "bridges",
+            join(self.bridges.iter().map(|b| &b.name)),
+        )])
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct NextReport {
+    /// The first address provided
+    ip: IpAddr,
+    /// All the addresses provided: two, an IPv4 and an IPv6 one, for a dual-stack pot
+    addresses: Vec<IpAddr>,
+    bridge: Option<String>,
+    /// The owner of the reservation, if the address has been reserved
+    owner: Option<String>,
+    /// The expiration of the reservation, in seconds since the epoch
+    expires: Option<u64>,
+}
+
+impl Report for NextReport {
+    fn text(&self, verbose: bool) -> String {
+        if verbose {
+            format!("{} available\n", join(self.addresses.iter()))
+        } else {
+            format!("{}\n", join(self.addresses.iter()))
+        }
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![("ip", join(self.addresses.iter()))])
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LeaseEntry {
+    ip: IpAddr,
+    owner: String,
+    expires: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ReleaseReport {
+    released: Vec<LeaseEntry>,
+}
+
+impl Report for ReleaseReport {
+    fn text(&self, verbose: bool) -> String {
+        if !verbose {
+            return String::new();
+        }
+        self.released
+            .iter()
+            .map(|l| format!("{} released ({})\n", l.ip, l.owner))
+            .collect()
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![("released", join(self.released.iter().map(|l| l.ip)))])
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ValidateReport {
+    ip: IpAddr,
+    bridge: Option<String>,
+    valid: bool,
+}
+
+impl Report for ValidateReport {
+    fn text(&self, _verbose: bool) -> String {
+        String::new()
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![("ip", self.ip.to_string())])
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct NewNetReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    net: IpNet,
+    gateway: IpAddr,
+}
+
+impl Report for NewNetReport {
+    // the bridge file format, an IPv6 network as the main network of the bridge
+    fn text(&self, _verbose: bool) -> String {
+        let name = match &self.name {
+            Some(name) => format!("name={}\n", name),
+            None => String::new(),
+        };
+        format!("{}net={}\ngateway={}\n", name, self.net, self.gateway)
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
         Some(
-            match ip_addr {
-                V4(_) => 32,
-                V6(_) => 128,
-            } - network_size,
+            self.name
+                .iter()
+                .map(|name| ("name", name.clone()))
+                .chain(vec![
+                    ("net", self.net.to_string()),
+                    ("gateway", self.gateway.to_string()),
+                ])
+                .collect(),
         )
-    } else {
-        None
     }
 }
 
-fn is_subnet_usable(subnet: IpNet, ip_db: &BTreeMap<IpAddr, Option<String>>) -> bool {
-    for ip in ip_db.keys() {
-        if subnet.contains(ip) {
-            return false;
+#[derive(Debug, Serialize)]
+struct PlanEntry {
+    name: String,
+    net: IpNet,
+    gateway: IpAddr,
+    /// The number of hosts asked for
+    hosts: u128,
+}
+
+#[derive(Debug, Serialize)]
+struct PlanReport {
+    bridges: Vec<PlanEntry>,
+    /// The free space of the pot network left by the plan
+    left: FreeReport,
+    /// True if the bridges have been created
+    applied: bool,
+}
+
+impl Report for PlanReport {
+    fn text(&self, verbose: bool) -> String {
+        let mut result: String = self
+            .bridges
+            .iter()
+            .map(|b| format!("{}\t{}\t{}\t{}\n", b.name, b.net, b.gateway, b.hosts))
+            .collect();
+        result.push_str(&self.left.text(verbose));
+        result
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![
+            ("bridges", join(self.bridges.iter().map(|b| &b.name))),
+            ("nets", join(self.bridges.iter().map(|b| b.net))),
+            ("left", join(self.left.ranges.iter().map(|e| e.range))),
+            ("left_addresses", self.left.free_addresses.to_string()),
+            ("applied", self.applied.to_string()),
+        ])
+    }
+}
+
+/// A range of free addresses and the networks covering it
+#[derive(Debug, Serialize)]
+struct FreeRangeEntry {
+    ip: IpAddr,
+    last: IpAddr,
+    nets: Vec<IpNet>,
+    #[serde(skip)]
+    range: IpRange,
+}
+
+#[derive(Debug, Serialize)]
+struct FreeReport {
+    network: IpNet,
+    /// The bridge the network belongs to, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bridge: Option<String>,
+    free_addresses: u128,
+    ranges: Vec<FreeRangeEntry>,
+    /// The biggest network that can be allocated
+    largest: Option<IpNet>,
+    /// From 0, all the free addresses in a single network, to 1, all scattered
+    fragmentation: f64,
+}
+
+impl FreeReport {
+    fn new(free: FreeSpace, bridge: Option<String>) -> Self {
+        FreeReport {
+            network: free.network,
+            bridge,
+            free_addresses: free.addresses,
+            largest: free.largest(),
+            fragmentation: free.fragmentation(),
+            ranges: free
+                .ranges
+                .iter()
+                .map(|range| FreeRangeEntry {
+                    ip: range.start,
+                    last: range.end,
+                    nets: range.to_nets(),
+                    range: *range,
+                })
+                .collect(),
         }
     }
-    true
 }
 
-fn new_net(host_number: u16, conf: &PotSystemConfig, ip_db: &BTreeMap<IpAddr, Option<String>>) {
-    if let Some(prefix_length) = get_prefix_length(host_number, &conf.gateway) {
-        info!("Subnet prefix length {}", prefix_length);
-        if let Ok(subnets) = conf.network.subnets(prefix_length) {
-            //info!("{} subnets to evaluate", subnets.count());
-            for s in subnets {
-                if is_subnet_usable(s, ip_db) {
-                    println!("net={}", s);
-                    println!("gateway={}", s.hosts().next().unwrap());
-                    break;
-                } else {
-                    debug!("{} not usable", s);
-                }
+impl Report for FreeReport {
+    fn text(&self, _verbose: bool) -> String {
+        let mut result = format!(
+            "Free addresses in {}: {}\n",
+            self.network, self.free_addresses
+        );
+        for entry in &self.ranges {
+            result.push_str(&format!("\t{}\t{}\n", entry.range, join(entry.nets.iter())));
+        }
+        if let Some(largest) = self.largest {
+            result.push_str(&format!(
+                "Largest free network: {} (prefix length {})\n",
+                largest,
+                largest.prefix_len()
+            ));
+        }
+        result.push_str(&format!("Fragmentation: {:.2}\n", self.fragmentation));
+        result
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![
+            ("network", self.network.to_string()),
+            ("free_addresses", self.free_addresses.to_string()),
+            ("ranges", join(self.ranges.iter().map(|e| e.range))),
+            ("nets", join(self.ranges.iter().flat_map(|e| e.nets.iter()))),
+            (
+                "largest",
+                self.largest.map(|n| n.to_string()).unwrap_or_default(),
+            ),
+            ("fragmentation", format!("{:.2}", self.fragmentation)),
+        ])
+    }
+}
+
+// the kind of the owner, followed by its name, if any
+fn describe_owner(owner: &Owner) -> String {
+    match owner.name() {
+        Some(name) => format!("{} {}", owner.kind(), name),
+        None => owner.kind().to_string(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DuplicateEntry {
+    ip: IpAddr,
+    owners: Vec<OwnerEntry>,
+    #[serde(skip)]
+    description: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DuplicatesReport {
+    duplicates: Vec<DuplicateEntry>,
+}
+
+impl Report for DuplicatesReport {
+    fn text(&self, _verbose: bool) -> String {
+        self.duplicates
+            .iter()
+            .map(|d| format!("{}\t{}\n", d.ip, d.description))
+            .collect()
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![(
+            "duplicates",
+            join(self.duplicates.iter().map(|d| d.ip)),
+        )])
+    }
+}
+
+fn duplicates(ip_db: &IpDb) -> DuplicatesReport {
+    DuplicatesReport {
+        duplicates: ip_db
+            .duplicates()
+            .map(|(ip, owners)| DuplicateEntry {
+                ip,
+                owners: owners.iter().map(OwnerEntry::from).collect(),
+                description: owners
+                    .iter()
+                    .map(describe_owner)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })
+            .collect(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HostEntry {
+    ip: IpAddr,
+    hostname: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EtcHostsReport {
+    hosts: Vec<HostEntry>,
+}
+
+impl Report for EtcHostsReport {
+    fn text(&self, _verbose: bool) -> String {
+        self.hosts
+            .iter()
+            .map(|h| format!("{} {}\n", h.ip, h.hostname))
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Finding {
+    #[serde(serialize_with = "serialize_display")]
+    severity: Severity,
+    /// The pot the finding is about, if any
+    pot: Option<String>,
+    message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.pot {
+            Some(pot) => write!(f, "pot {}: {}", pot, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ConfigCheckReport {
+    ok: bool,
+    /// The severity of the worst finding
+    #[serde(serialize_with = "serialize_display_opt")]
+    worst: Option<Severity>,
+    findings: Vec<Finding>,
+}
+
+impl Report for ConfigCheckReport {
+    // in text mode, findings are logged
+    fn text(&self, _verbose: bool) -> String {
+        String::new()
+    }
+
+    fn shell(&self) -> Option<Vec<(&'static str, String)>> {
+        let count = |severity| {
+            self.findings
+                .iter()
+                .filter(|f| f.severity == severity)
+                .count()
+        };
+        Some(vec![
+            ("ok", if self.ok { "yes" } else { "no" }.to_string()),
+            (
+                "worst",
+                self.worst.map(|s| s.to_string()).unwrap_or_default(),
+            ),
+            ("errors", count(Severity::Error).to_string()),
+            ("warnings", count(Severity::Warning).to_string()),
+        ])
+    }
+}
+
+fn show(conf: &PotSystemConfig, ip_db: &IpDb, reports: &[PotConfReport]) -> ShowReport {
+    let mut problems = Vec::new();
+    for report in reports {
+        for diagnostic in &report.diagnostics {
+            problems.push(Problem {
+                pot: report.name.clone(),
+                severity: diagnostic.severity,
+                file: diagnostic.file.clone(),
+                line: diagnostic.line,
+                key: diagnostic.key.clone(),
+                reason: diagnostic.reason.clone(),
+                description: diagnostic.to_string(),
+            });
+        }
+    }
+    ShowReport {
+        network: conf.network.trunc(),
+        min_addr: conf.network.network(),
+        max_addr: conf.network.broadcast(),
+        network6: conf.network6.map(|n| n.trunc()),
+        addresses: address_entries(ip_db),
+        problems,
+        debug: format!("{:#?}", conf),
+    }
+}
+
+fn show_bridge(conf: &PotSystemConfig, bridge_name: &str) -> Result<BridgeReport> {
+    let bridge = get_bridge(conf, bridge_name)?;
+    let ip_db = IpDb::load_bridge(conf, &bridge)?;
+    Ok(BridgeReport {
+        bridge: bridge.name,
+        network: bridge.network,
+        gateway: bridge.gateway,
+        network6: bridge.network6,
+        gateway6: bridge.gateway6,
+        mtu: bridge.mtu,
+        vlan: bridge.vlan,
+        description: bridge.description,
+        isolated: bridge.isolated,
+        addresses: address_entries(&ip_db),
+    })
+}
+
+// the network asked for, or the first free subnet of the pot network big enough
+fn bridge_network(conf: &PotSystemConfig, ip_db: &IpDb, bopt: &BridgeSizeOpt) -> Result<IpNet> {
+    match (bopt.net, bopt.host_number) {
+        (Some(net), _) => Ok(net.trunc()),
+        (None, Some(n)) => Ok(ip_db.new_subnet(&conf.network, n)?),
+        (None, None) => bail!("either the network or the number of hosts is needed"),
+    }
+}
+
+fn create(
+    conf: &PotSystemConfig,
+    ip_db: &IpDb,
+    bopt: &BridgeSizeOpt,
+) -> Result<BridgeDefinitionReport> {
+    let bridge = BridgeConf::new(&bopt.bridge_name, bridge_network(conf, ip_db, bopt)?)?;
+    create_bridge(conf, &bridge)?;
+    info!("bridge {} created", bridge.name);
+    Ok(bridge.into())
+}
+
+fn resize(conf: &PotSystemConfig, bopt: &BridgeSizeOpt) -> Result<BridgeDefinitionReport> {
+    let net = match (bopt.net, bopt.host_number) {
+        (Some(net), _) => net.trunc(),
+        // the network grows, or shrinks, from its first address
+        (None, Some(n)) => {
+            if n <= 1 {
+                return Err(PotError::NetworkTooSmall(n).into());
             }
+            let current = get_bridge(conf, &bopt.bridge_name)?.network;
+            get_prefix_length(n, &current.addr())
+                .and_then(|prefix_len| new_net(current.network(), prefix_len))
+                .map(|net| net.trunc())
+                .ok_or(PotError::NoSubnetAvailable)?
+        }
+        (None, None) => bail!("either the network or the number of hosts is needed"),
+    };
+    Ok(resize_bridge(conf, &bopt.bridge_name, net)?.into())
+}
+
+// the network to carve a new subnet from: --within, that has to be in a pool, or a pool
+fn new_net_within(conf: &PotSystemConfig, nopt: &NewNetOpt) -> Result<IpNet> {
+    let ipv6 = match (nopt.within, nopt.family) {
+        (Some(within), _) => is_ipv6_net(&within),
+        (None, Some(IpStack::Ipv4)) => false,
+        (None, Some(IpStack::Ipv6)) => true,
+        (None, Some(IpStack::Dual)) => bail!("new-net provides a network of a single family"),
+        (None, None) => return Ok(conf.network),
+    };
+    let pool = conf
+        .pool_for(ipv6)
+        .ok_or(PotError::PoolNotConfigured(if ipv6 { 6 } else { 4 }))?;
+    match nopt.within {
+        Some(within) if !pool.network.contains(&within) => {
+            Err(PotError::AddressOutsideNetwork(within.addr(), pool.network).into())
         }
+        Some(within) => Ok(within.trunc()),
+        None => Ok(pool.network),
     }
 }
 
-fn get_next_from_bridge(opt: &Opt, conf: &PotSystemConfig, bridge_name: &str) -> Result<()> {
-    let bridges_list = get_bridges_list(conf)?;
-    if let Some(bridge) = bridges_list.iter().find(|x| x.name == bridge_name) {
-        info!("bridge {} found", bridge.name);
-        let mut ip_db = BTreeMap::new();
-        init_bridge_ipdb(&bridge, conf, &mut ip_db);
-        for addr in bridge.network.hosts() {
-            if !ip_db.contains_key(&addr) {
-                if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
-                    println!("{} available", addr);
-                } else {
-                    println!("{}", addr);
+fn new_network(conf: &PotSystemConfig, ip_db: &IpDb, nopt: &NewNetOpt) -> Result<NewNetReport> {
+    let within = new_net_within(conf, nopt)?;
+    let prefix_len = match (nopt.prefix_len, nopt.host_number) {
+        (Some(prefix_len), _) => prefix_len,
+        (None, Some(n)) if n <= 1 => return Err(PotError::NetworkTooSmall(n).into()),
+        (None, Some(n)) => get_prefix_length(n, &within.addr())
+            .filter(|prefix_len| *prefix_len >= within.prefix_len())
+            .ok_or(PotError::NoSubnetAvailable)?,
+        (None, None) => bail!("either the prefix length or the number of hosts is needed"),
+    };
+    debug!("{} fit of a /{} in {}", nopt.fit, prefix_len, within);
+    let net = ip_db.new_subnet_with(&within, prefix_len, nopt.fit)?;
+    info!("Subnet prefix length {}", net.prefix_len());
+    Ok(NewNetReport {
+        name: nopt.bridge_name.clone(),
+        net,
+        gateway: BridgeConf::first_gateway(&net)?,
+    })
+}
+
+// the largest bridges are placed first, as new-net would, then the bridges are created if asked
+fn plan_nets(conf: &PotSystemConfig, ip_db: &IpDb, popt: &PlanOpt) -> Result<PlanReport> {
+    let plan = ip_db.plan_subnets(&conf.network, &popt.sizes.0)?;
+    let bridges = plan
+        .iter()
+        .map(|(name, net)| BridgeConf::new(name, *net))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if popt.apply {
+        create_bridges(conf, &bridges)?;
+        for bridge in &bridges {
+            info!("bridge {} created", bridge.name);
+        }
+    } else {
+        for bridge in bridges
+            .iter()
+            .filter(|b| get_bridge_path(conf, &b.name).exists())
+        {
+            warn!("bridge {} already exists", bridge.name);
+        }
+    }
+    let mut planned = ip_db.clone();
+    for (name, net) in &plan {
+        planned.fill_range(
+            IpRange::from_net(net),
+            Owner::BridgeRange {
+                bridge: name.clone(),
+            },
+        );
+    }
+    let left = FreeReport::new(planned.free_space(&conf.network), None);
+    Ok(PlanReport {
+        bridges: bridges
+            .into_iter()
+            .map(|b| PlanEntry {
+                hosts: popt
+                    .sizes
+                    .0
+                    .iter()
+                    .find(|(name, _)| *name == b.name)
+                    .map(|(_, size)| *size)
+                    .unwrap_or_default(),
+                name: b.name,
+                net: b.network,
+                gateway: b.gateway,
+            })
+            .collect(),
+        left,
+        applied: popt.apply,
+    })
+}
+
+fn free(conf: &PotSystemConfig, ip_db: &IpDb, fopt: &FreeOpt) -> Result<FreeReport> {
+    if let Some(bridge_name) = &fopt.bridge_name {
+        let bridge = get_bridge(conf, bridge_name)?;
+        let free = IpDb::load_bridge(conf, &bridge)?.free_space(&bridge.network);
+        return Ok(FreeReport::new(free, Some(bridge.name)));
+    }
+    let network = match fopt.family {
+        None => conf.network,
+        Some(IpStack::Dual) => bail!("free reports the network of a single family"),
+        Some(family) => {
+            let ipv6 = family == IpStack::Ipv6;
+            conf.pool_for(ipv6)
+                .ok_or(PotError::PoolNotConfigured(if ipv6 { 6 } else { 4 }))?
+                .network
+        }
+    };
+    Ok(FreeReport::new(ip_db.free_space(&network), None))
+}
+
+fn list_bridges(conf: &PotSystemConfig) -> Result<BridgesReport> {
+    let mut bridges = get_bridges_list(conf)?;
+    bridges.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(BridgesReport {
+        bridges: bridges
+            .into_iter()
+            .map(|b| BridgeEntry {
+                pots: get_bridge_members(conf, &b)
+                    .into_iter()
+                    .map(|p| p.name)
+                    .collect(),
+                name: b.name,
+                net: b.network,
+                gateway: b.gateway,
+            })
+            .collect(),
+    })
+}
+
+fn next(
+    conf: &PotSystemConfig,
+    ip_db: &IpDb,
+    nopt: &NextOpt,
+    lease_db: Option<&mut LeaseDb>,
+) -> Result<NextReport> {
+    let kind = nopt.strategy.unwrap_or(conf.ip_allocation);
+    debug!("allocation strategy {}", kind);
+    let strategy = kind.build(conf)?;
+    let pot = nopt.for_pot.as_deref();
+    let bridge_db;
+    let (ip_db, addrs, networks) = if let Some(bridge_name) = &nopt.bridge_name {
+        debug!("get an ip for the bridge {}", bridge_name);
+        let bridge = get_bridge(conf, bridge_name)?;
+        bridge_db = IpDb::load_bridge(conf, &bridge)?;
+        let addr = bridge_db.next_for(&bridge.network, pot, strategy.as_ref())?;
+        (&bridge_db, vec![addr], vec![bridge.network])
+    } else {
+        let addrs = ip_db.next_in_pools(conf, nopt.family, pot, strategy.as_ref())?;
+        let networks = conf.pools().iter().map(|p| p.network).collect();
+        (ip_db, addrs, networks)
+    };
+    let addr = addrs[0];
+    let mut report = NextReport {
+        ip: addr,
+        addresses: addrs.clone(),
+        bridge: nopt.bridge_name.clone(),
+        owner: None,
+        expires: None,
+    };
+    if let (Some(lease_db), Some(owner)) = (lease_db, &nopt.owner) {
+        for a in &addrs {
+            lease_db.reserve(*a, owner, Duration::from_secs(nopt.ttl))?;
+        }
+        lease_db.save()?;
+        // recorded only for the addresses actually taken, while the lease database is locked;
+        // the reserved address of a pot doesn't move the round-robin position
+        if kind == Strategy::RoundRobin {
+            for a in &addrs {
+                let network = match networks.iter().find(|n: &&IpNet| n.contains(a)) {
+                    Some(network) => network,
+                    None => continue,
+                };
+                let reserved = match pot {
+                    Some(pot) => ip_db.reserved_for(pot, network)?,
+                    None => None,
+                };
+                if reserved != Some(*a) {
+                    save_last_allocation(conf, network, *a)?;
                 }
-                break;
             }
         }
-    } else {
-        error!("bridge {} not found", bridge_name);
+        report.owner = Some(owner.clone());
+        report.expires = lease_db.get(addr).map(|l| l.expires);
     }
-    Ok(())
+    Ok(report)
 }
 
-fn get_hosts_from_bridge(_opt: &Opt, conf: &PotSystemConfig, bridge_name: &str) -> Result<()> {
-    let bridges_list = get_bridges_list(conf)?;
-    if let Some(bridge) = bridges_list.iter().find(|x| x.name == bridge_name) {
-        info!("bridge {} found", bridge.name);
-        let mut ip_db = BTreeMap::new();
-        info!("Evaluating bridge {:?}", bridge);
-        for v in &get_pot_conf_list(conf.clone()) {
-            if v.network_type == NetType::PrivateBridge
-                && bridge.network.contains(&v.ip_addr.unwrap())
-            {
-                ip_db.insert(v.ip_addr.unwrap(), v.name.clone());
+fn release(conf: &PotSystemConfig, ropt: &ReleaseOpt) -> Result<ReleaseReport> {
+    let mut lease_db = LeaseDb::open(conf)?;
+    let released = if let Some(owner) = &ropt.owner {
+        lease_db.release_owner(owner)
+    } else if let Some(ip) = ropt.ip {
+        lease_db.release(ip).into_iter().collect()
+    } else {
+        Vec::new()
+    };
+    if released.is_empty() {
+        warn!("no reservation found");
+    } else {
+        lease_db.save()?;
+        if conf.ip_quarantine > 0 {
+            let mut quarantine_db = QuarantineDb::open(conf)?;
+            for l in &released {
+                quarantine_db.add(l.ip, &l.owner);
             }
+            quarantine_db.save()?;
         }
-        for (ip, hostname) in ip_db {
-            println!("{} {}", ip, hostname);
+    }
+    Ok(ReleaseReport {
+        released: released
+            .into_iter()
+            .map(|l| LeaseEntry {
+                ip: l.ip,
+                owner: l.owner,
+                expires: l.expires,
+            })
+            .collect(),
+    })
+}
+
+// quarantine the addresses of the pots destroyed since the previous run;
+// a pot whose configuration can't be understood is not considered destroyed
+fn update_quarantine(conf: &PotSystemConfig, reports: &[PotConfReport]) -> Result<()> {
+    let mut quarantine_db = QuarantineDb::open(conf)?;
+    let mut pots = Vec::new();
+    let mut unreadable = Vec::new();
+    for report in reports {
+        match &report.conf {
+            Some(v) if !report.has_errors() => {
+                if v.network_type == NetType::PublicBridge
+                    || v.network_type == NetType::PrivateBridge
+                {
+                    pots.extend(v.ip_addrs.iter().map(|ip| (*ip, v.name.clone())));
+                }
+            }
+            _ => unreadable.push(report.name.as_str()),
         }
     }
+    for q in quarantine_db.update_pots(&pots, &unreadable) {
+        info!("{} released by {}, in quarantine", q.ip, q.owner);
+    }
+    quarantine_db.save()?;
     Ok(())
 }
 
-fn get_hosts_for_public_bridge(_opt: &Opt, conf: &PotSystemConfig) {
+fn get_hosts_from_bridge(conf: &PotSystemConfig, bridge_name: &str) -> Result<EtcHostsReport> {
+    let bridge = get_bridge(conf, bridge_name)?;
+    let mut ip_db = BTreeMap::new();
+    info!("Evaluating bridge {:?}", bridge);
+    for v in get_bridge_members(conf, &bridge) {
+        if let Some(ip_addr) = v.ip_addr {
+            ip_db.insert(ip_addr, v.name);
+        }
+    }
+    Ok(EtcHostsReport {
+        hosts: ip_db
+            .into_iter()
+            .map(|(ip, hostname)| HostEntry { ip, hostname })
+            .collect(),
+    })
+}
+
+fn get_hosts_for_public_bridge(conf: &PotSystemConfig) -> EtcHostsReport {
     let mut ip_db = BTreeMap::new();
     for v in &get_pot_conf_list(conf.clone()) {
         if v.network_type == NetType::PublicBridge {
             ip_db.insert(v.ip_addr.unwrap(), v.name.clone());
         }
     }
-    for (ip, hostname) in ip_db {
-        println!("{} {}", ip, hostname);
+    EtcHostsReport {
+        hosts: ip_db
+            .into_iter()
+            .map(|(ip, hostname)| HostEntry { ip, hostname })
+            .collect(),
     }
 }
 
-fn validate_with_bridge(conf: &PotSystemConfig, bridge_name: &str, ip: IpAddr) -> Result<()> {
-    let bridges_list = get_bridges_list(conf)?;
-    if let Some(bridge) = bridges_list.iter().find(|x| x.name == bridge_name) {
-        info!("bridge {} found", bridge.name);
-        let mut ip_db = BTreeMap::new();
-        init_bridge_ipdb(&bridge, conf, &mut ip_db);
-        // the ip address is in the bridge network
-        if !bridge.network.contains(&ip) {
-            error!("ip {} not in the bridge network {}", ip, bridge.network);
-            bail!("Ip outside the bridge network");
+// reservations outside their network, colliding with another pot or with each other
+fn check_reservations(conf: &PotSystemConfig) -> Vec<Finding> {
+    let error = |pot: &str, message: String| Finding {
+        severity: Severity::Error,
+        pot: Some(pot.to_string()),
+        message,
+    };
+    let (reservations, bridges) = match (get_reservations(conf), get_bridges_list(conf)) {
+        (Ok(reservations), Ok(bridges)) => (reservations, bridges),
+        (Err(e), _) | (_, Err(e)) => {
+            return vec![Finding {
+                severity: Severity::Error,
+                pot: None,
+                message: e.to_string(),
+            }]
+        }
+    };
+    let pots = get_pot_conf_list(conf.clone());
+    let mut findings = Vec::new();
+    let mut seen: BTreeMap<IpAddr, &str> = BTreeMap::new();
+    let mut seen_pots: BTreeMap<(&str, IpNet), IpAddr> = BTreeMap::new();
+    for r in &reservations {
+        let network = match &r.bridge {
+            Some(name) => match bridges.iter().find(|b| &b.name == name) {
+                Some(bridge) => Some(bridge.network),
+                None => {
+                    findings.push(error(
+                        &r.pot,
+                        format!("reservation {}: bridge {} not found", r.ip, name),
+                    ));
+                    continue;
+                }
+            },
+            None => conf.pool_for(r.ip.is_ipv6()).map(|pool| pool.network),
+        };
+        match network {
+            Some(network) if network.contains(&r.ip) => {
+                match seen_pots.insert((&r.pot, network), r.ip) {
+                    Some(other) if other != r.ip => findings.push(error(
+                        &r.pot,
+                        format!(
+                            "reservation {}: {} already reserved in the network {}",
+                            r.ip, other, network
+                        ),
+                    )),
+                    _ => (),
+                }
+            }
+            Some(network) => findings.push(error(
+                &r.pot,
+                format!("reservation {} outside the network {}", r.ip, network),
+            )),
+            None => findings.push(error(
+                &r.pot,
+                format!("reservation {}: no network of its family", r.ip),
+            )),
+        }
+        if let Some(other) = pots
+            .iter()
+            .find(|p| p.name != r.pot && p.ip_addrs.contains(&r.ip))
+        {
+            findings.push(error(
+                &r.pot,
+                format!(
+                    "reservation {} already used by the pot {}",
+                    r.ip, other.name
+                ),
+            ));
         }
-        // the ip is already in use
-        if ip_db.contains_key(&ip) {
-            error!("ip {} already in use", ip);
-            bail!("Ip already used");
+        if let Some(other) = seen.insert(r.ip, &r.pot) {
+            findings.push(error(
+                &r.pot,
+                format!("reservation {} already reserved for {}", r.ip, other),
+            ));
         }
+    }
+    findings
+}
+
+fn is_ipv6_net(network: &IpNet) -> bool {
+    matches!(network, IpNet::V6(_))
+}
+
+// an address of the system configuration outside its network, or of the other family
+fn check_address(what: &str, ip: IpAddr, network: &IpNet) -> Option<String> {
+    if ip.is_ipv6() != is_ipv6_net(network) {
+        Some(format!(
+            "{} ({}) and the network range ({}) are of different families",
+            what, ip, network
+        ))
+    } else if !network.contains(&ip) {
+        Some(format!(
+            "{} ({}) outside the network range ({})",
+            what, ip, network
+        ))
     } else {
-        bail!("bridge {} not found", bridge_name);
+        None
     }
-    Ok(())
 }
 
-fn validate(
-    ip: IpAddr,
+// pots with an address outside the networks of their type
+fn check_pots(
     conf: &PotSystemConfig,
-    ip_db: &BTreeMap<IpAddr, Option<String>>,
-) -> Result<()> {
-    if ip_db.contains_key(&ip) {
-        bail!("Address already in use");
-    }
-    if !conf.network.contains(&ip) {
-        bail!("Address outside the network");
+    reports: &[PotConfReport],
+    bridges: &[BridgeConf],
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for pot in reports.iter().filter_map(|r| r.conf.as_ref()) {
+        let mut error = |message| {
+            findings.push(Finding {
+                severity: Severity::Error,
+                pot: Some(pot.name.clone()),
+                message,
+            })
+        };
+        for ip in &pot.ip_addrs {
+            match pot.network_type {
+                NetType::PublicBridge => match conf.pool_for(ip.is_ipv6()) {
+                    Some(pool) if pool.network.contains(ip) => (),
+                    Some(pool) => error(format!(
+                        "address {} outside the network range ({})",
+                        ip, pool.network
+                    )),
+                    None => error(format!(
+                        "address {} of a family without a network range",
+                        ip
+                    )),
+                },
+                NetType::PrivateBridge if !bridges.iter().any(|b| b.network.contains(ip)) => {
+                    error(format!("address {} outside any private bridge", ip));
+                }
+                _ => (),
+            }
+        }
     }
-    Ok(())
+    findings
 }
 
-fn init_bridge_ipdb(
-    bridge: &BridgeConf,
+fn config_check(
     conf: &PotSystemConfig,
-    ip_db: &mut BTreeMap<IpAddr, Option<String>>,
-) {
-    info!("Evaluating bridge {:?}", bridge);
-    // add the network address
-    let mut description = String::from(bridge.name.as_str());
-    description.push_str(" bridge - network ");
-    ip_db.insert(bridge.network.network(), Some(description));
-    // add the broadcast address
-    let mut description = String::from(bridge.name.as_str());
-    description.push_str(" bridge - broadcast ");
-    ip_db.insert(bridge.network.broadcast(), Some(description));
-    // add the broadcast address
-    let mut description = String::from(bridge.name.as_str());
-    description.push_str(" bridge - gateway ");
-    ip_db.insert(bridge.gateway, Some(description));
-    for v in &get_pot_conf_list(conf.clone()) {
-        if (v.network_type == NetType::PublicBridge || v.network_type == NetType::PrivateBridge)
-            && bridge.network.contains(&v.ip_addr.unwrap())
+    ip_db: &IpDb,
+    reports: &[PotConfReport],
+) -> ConfigCheckReport {
+    let mut findings = Vec::new();
+    let mut system_error = |message: String| {
+        findings.push(Finding {
+            severity: Severity::Error,
+            pot: None,
+            message,
+        })
+    };
+    if let Some(message) = check_address("gateway IP", conf.gateway, &conf.network) {
+        system_error(message);
+    }
+    if let Some(message) = check_address("DNS IP", conf.dns_ip, &conf.network) {
+        system_error(message);
+    }
+    if conf.network.netmask() != conf.netmask {
+        system_error(format!(
+            "netmask ({}) different from the network one ({})",
+            conf.netmask, conf.network
+        ));
+    }
+    if let Some(pool6) = conf.pool6() {
+        if is_ipv6_net(&conf.network) {
+            system_error(format!(
+                "POT_NETWORK ({}) and POT_NETWORK6 ({}) are both IPv6 networks",
+                conf.network, pool6.network
+            ));
+        }
+        if let Some(message) = check_address("IPv6 gateway IP", pool6.gateway, &pool6.network) {
+            system_error(message);
+        }
+        if let Some(dns_ip6) = pool6.dns_ip {
+            if let Some(message) = check_address("IPv6 DNS IP", dns_ip6, &pool6.network) {
+                system_error(message);
+            }
+        }
+    }
+    for range in &conf.reserved {
+        if !conf
+            .pools()
+            .iter()
+            .any(|p| p.network.contains(&range.start) && p.network.contains(&range.end))
         {
-            ip_db.insert(v.ip_addr.unwrap(), Some(v.name.clone()));
+            findings.push(Finding {
+                severity: Severity::Warning,
+                pot: None,
+                message: format!(
+                    "POT_NETWORK_RESERVED range {} outside the network ranges",
+                    range
+                ),
+            });
         }
     }
+    let (bridge_reports, bridge_findings) = check_bridges(conf);
+    for diagnostic in bridge_reports.iter().flat_map(|r| &r.diagnostics) {
+        findings.push(Finding {
+            severity: diagnostic.severity,
+            pot: None,
+            message: diagnostic.to_string(),
+        });
+    }
+    let bridges: Vec<BridgeConf> = bridge_reports.into_iter().filter_map(|r| r.conf).collect();
+    findings.extend(bridge_findings.iter().map(|f| Finding {
+        severity: f.problem.severity(),
+        pot: None,
+        message: f.to_string(),
+    }));
+    findings.extend(check_pots(conf, reports, &bridges));
+    for d in duplicates(ip_db).duplicates {
+        findings.push(Finding {
+            severity: Severity::Error,
+            pot: None,
+            message: format!("address {} claimed by: {}", d.ip, d.description),
+        });
+    }
+    findings.extend(check_reservations(conf));
+    for report in reports {
+        for diagnostic in &report.diagnostics {
+            findings.push(Finding {
+                severity: diagnostic.severity,
+                pot: Some(report.name.clone()),
+                message: diagnostic.to_string(),
+            });
+        }
+    }
+    let worst = findings.iter().map(|f| f.severity).max();
+    ConfigCheckReport {
+        ok: worst != Some(Severity::Error),
+        worst,
+        findings,
+    }
 }
 
-fn init_ipdb(conf: &PotSystemConfig, ip_db: &mut BTreeMap<IpAddr, Option<String>>) -> Result<()> {
-    info!("Insert network {:?}", conf.network);
-    ip_db.insert(conf.network.network(), None);
-    info!("Insert broadcast {:?}", conf.network);
-    ip_db.insert(conf.network.broadcast(), None);
-    info!("Insert gateway {:?}", conf.gateway);
-    ip_db.insert(conf.gateway, Some("default gateway".to_string()));
-    info!("Insert dns {:?}", conf.dns_ip);
-    ip_db.insert(conf.dns_ip, Some(conf.dns_name.clone()));
-    for v in &get_pot_conf_list(conf.clone()) {
-        if v.network_type == NetType::PublicBridge || v.network_type == NetType::PrivateBridge {
-            info!("Insert pot {:?}", v.ip_addr.unwrap());
-            ip_db.insert(v.ip_addr.unwrap(), Some(v.name.clone()));
-        }
-    }
-    for b in &get_bridges_list(conf)? {
-        info!("Evaluating bridge {:?}", b);
-        // add the network address
-        let mut description = String::from(b.name.as_str());
-        description.push_str(" bridge - network ");
-        ip_db.insert(b.network.network(), Some(description));
-        // add the broadcast address
-        let mut description = String::from(b.name.as_str());
-        description.push_str(" bridge - broadcast ");
-        ip_db.insert(b.network.broadcast(), Some(description));
-        // add the broadcast address
-        let mut description = String::from(b.name.as_str());
-        description.push_str(" bridge - gateway ");
-        ip_db.insert(b.gateway, Some(description));
-        // add all the not yet allocated hosts
-        let mut description = String::from(b.name.as_str());
-        description.push_str(" bridge - allocated address");
-        for host in b.network.hosts() {
-            ip_db
-                .entry(host)
-                .or_insert_with(|| Some(description.clone()));
+// pots with errors are not in the ip database: their addresses could be handed out again
+fn warn_pot_problems(reports: &[PotConfReport]) {
+    for report in reports.iter().filter(|r| r.has_errors()) {
+        warn!(
+            "pot {} ignored, its address could be already in use",
+            report.name
+        );
+        for diagnostic in &report.diagnostics {
+            warn!("{}", diagnostic);
         }
     }
-    Ok(())
 }
 
-fn main() -> Result<()> {
-    let opt = Opt::from_args();
-    opt.verbose.set_log_level();
-    trace!("potnet start");
+// broken bridge files are ignored, the problems of the others are reported only
+fn warn_bridge_problems(conf: &PotSystemConfig) {
+    let (reports, findings) = check_bridges(conf);
+    for report in reports.iter().filter(|r| r.has_errors()) {
+        if report.conf.is_none() {
+            warn!("bridge file {} ignored", report.path.display());
+        }
+        for diagnostic in &report.diagnostics {
+            warn!("{}", diagnostic);
+        }
+    }
+    for finding in findings {
+        warn!("{}", finding);
+    }
+}
 
-    let conf = PotSystemConfig::from_system()?;
-    let mut ip_db = BTreeMap::new();
-    init_ipdb(&conf, &mut ip_db)?;
-    let opt_clone = opt.clone();
-    match opt.subcommand {
+fn load_conf(opt: &Opt) -> Result<PotSystemConfig> {
+    let conf = if let Some(config) = &opt.config {
+        PotSystemConfig::from_conf(config)?
+    } else if let Some(prefix) = &opt.prefix {
+        PotSystemConfig::from_prefix(prefix)?
+    } else {
+        PotSystemConfig::from_system()?
+    };
+    if let Some(source) = &conf.source {
+        info!("configuration read from {}", source);
+    }
+    Ok(conf)
+}
+
+fn run(opt: &Opt) -> Result<()> {
+    let conf = load_conf(opt)?;
+    // the lease database is locked before the scan, to reserve the address atomically
+    let mut lease_db = match &opt.subcommand {
+        Command::Next(nopt) if nopt.reserve => Some(LeaseDb::open(&conf)?),
+        _ => None,
+    };
+    let reports = get_pot_conf_reports(&conf);
+    // only the commands handing out or releasing addresses update the quarantine
+    if conf.ip_quarantine > 0 && matches!(opt.subcommand, Command::Next(_) | Command::Release(_)) {
+        update_quarantine(&conf, &reports)?;
+    }
+    let ip_db = IpDb::load(&conf)?;
+    match &opt.subcommand {
         Command::Show(bopt) => {
-            if let Some(bridge_name) = bopt.bridge_name {
-                show_bridge(&opt_clone, &conf, &bridge_name)?;
+            warn_bridge_problems(&conf);
+            if let Some(bridge_name) = &bopt.bridge_name {
+                print_report(opt, &show_bridge(&conf, bridge_name)?)?;
             } else {
-                show(&opt_clone, &conf, &mut ip_db);
+                print_report(opt, &show(&conf, &ip_db, &reports))?;
             }
         }
         Command::Next(nopt) => {
-            if let Some(bridge_name) = nopt.bridge_name {
-                debug!("get an ip for the bridge {}", bridge_name);
-                get_next_from_bridge(&opt_clone, &conf, &bridge_name)?;
-            } else {
-                get(&opt_clone, &conf, &ip_db);
-            }
+            warn_pot_problems(&reports);
+            warn_bridge_problems(&conf);
+            let report = next(&conf, &ip_db, nopt, lease_db.as_mut())?;
+            print_report(opt, &report)?;
         }
+        Command::Release(ropt) => print_report(opt, &release(&conf, ropt)?)?,
         Command::Validate(vopt) => {
-            if let Some(bridge_name) = vopt.bridge_name {
-                debug!(
-                    "validate the ip {} for the bridge {}",
-                    &vopt.ip.host_addr, bridge_name
-                );
-                return validate_with_bridge(&conf, &bridge_name, vopt.ip.host_addr);
+            warn_pot_problems(&reports);
+            warn_bridge_problems(&conf);
+            let ip = vopt.ip.host_addr;
+            if let Some(bridge_name) = &vopt.bridge_name {
+                debug!("validate the ip {} for the bridge {}", ip, bridge_name);
+                let bridge = get_bridge(&conf, bridge_name)?;
+                IpDb::load_bridge(&conf, &bridge)?.validate_in(
+                    &bridge.network,
+                    ip,
+                    vopt.for_pot.as_deref(),
+                )?;
             } else {
-                return validate(vopt.ip.host_addr, &conf, &ip_db);
+                ip_db.validate(&conf, ip, vopt.for_pot.as_deref())?;
             }
+            print_report(
+                opt,
+                &ValidateReport {
+                    ip,
+                    bridge: vopt.bridge_name.clone(),
+                    valid: true,
+                },
+            )?;
         }
         Command::IP4(x) => {
             if !x.ip.host_addr.is_ipv4() {
@@ -393,102 +1721,160 @@ fn main() -> Result<()> {
             debug!("{} is a valid IP address", x.ip.host_addr);
         }
         Command::ConfigCheck => {
-            if !conf.network.contains(&conf.gateway) {
-                error!(
-                    "gateway IP ({}) outside the network range ({})",
-                    conf.gateway, conf.network
-                );
-            }
-            if !conf.network.contains(&conf.dns_ip) {
-                error!(
-                    "DNS IP ({}) outside the network range ({})",
-                    conf.dns_ip, conf.network
-                );
-            }
-            if conf.network.netmask() != conf.netmask {
-                error!(
-                    "netmask ({}) different from the network one ({})",
-                    conf.netmask, conf.network
-                );
+            let report = config_check(&conf, &ip_db, &reports);
+            if opt.output == OutputFormat::Text {
+                for finding in &report.findings {
+                    match finding.severity {
+                        Severity::Error => error!("{}", finding),
+                        Severity::Warning => warn!("{}", finding),
+                    }
+                }
             }
-            if !conf.network.contains(&conf.gateway) || conf.network.netmask() != conf.netmask {
-                std::process::exit(1);
+            print_report(opt, &report)?;
+            match report.worst {
+                Some(Severity::Error) => std::process::exit(1),
+                Some(Severity::Warning) => std::process::exit(2),
+                None => (),
             }
         }
         Command::NewNetwork(x) => {
-            if x.host_number <= 1 {
-                error!("A network with size {} is too small", x.host_number);
-                std::process::exit(1);
-            }
-            new_net(x.host_number, &conf, &ip_db);
+            warn_pot_problems(&reports);
+            warn_bridge_problems(&conf);
+            print_report(opt, &new_network(&conf, &ip_db, x)?)?;
         }
+        Command::PlanNets(popt) => {
+            warn_pot_problems(&reports);
+            warn_bridge_problems(&conf);
+            print_report(opt, &plan_nets(&conf, &ip_db, popt)?)?;
+        }
+        Command::Free(fopt) => {
+            warn_bridge_problems(&conf);
+            print_report(opt, &free(&conf, &ip_db, fopt)?)?;
+        }
+        Command::Duplicates => print_report(opt, &duplicates(&ip_db))?,
+        Command::Bridge(BridgeCommand::Create(bopt)) => {
+            print_report(opt, &create(&conf, &ip_db, bopt)?)?
+        }
+        Command::Bridge(BridgeCommand::Delete(bopt)) => {
+            let bridge = delete_bridge(&conf, &bopt.bridge_name)?;
+            info!("bridge {} deleted", bridge.name);
+            print_report(opt, &BridgeDefinitionReport::from(bridge))?;
+        }
+        Command::Bridge(BridgeCommand::Resize(bopt)) => print_report(opt, &resize(&conf, bopt)?)?,
+        Command::Bridge(BridgeCommand::List) => print_report(opt, &list_bridges(&conf)?)?,
         Command::EtcHosts(ehopt) => {
-            if let Some(bridge_name) = ehopt.bridge_name {
-                debug!("get an ip for the bridge {}", bridge_name);
-                get_hosts_from_bridge(&opt_clone, &conf, &bridge_name)?;
+            let report = if let Some(bridge_name) = &ehopt.bridge_name {
+                debug!("get the hosts of the bridge {}", bridge_name);
+                get_hosts_from_bridge(&conf, bridge_name)?
             } else {
-                get_hosts_for_public_bridge(&opt_clone, &conf);
-            }
+                get_hosts_for_public_bridge(&conf)
+            };
+            print_report(opt, &report)?;
         }
     }
     Ok(())
 }
 
+fn main() {
+    let opt = Opt::from_args();
+    opt.verbose.set_log_level();
+    trace!("potnet start");
+    if let Err(e) = run(&opt) {
+        print_error(&opt, &e);
+        std::process::exit(1);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::{Ipv4Addr, Ipv6Addr};
 
     #[test]
-    fn get_network_size_000() {
-        let uut = get_network_size(2);
-        assert_eq!(uut, Some(2));
-    }
-    #[test]
-    fn get_network_size_001() {
-        let uut = get_network_size(5);
-        assert_eq!(uut, Some(3));
-    }
-    #[test]
-    fn get_network_size_002() {
-        let uut = get_network_size(7);
-        assert_eq!(uut, Some(4));
+    fn error_code_001() {
+        let e = anyhow::Error::from(PotError::AddressInUse("10.192.0.3".parse().unwrap()));
+        assert_eq!(error_code(&e), "address-in-use");
+        let e = anyhow::Error::from(PotnetError::UnsupportedOutput("shell"));
+        assert_eq!(error_code(&e), "unsupported-output");
+        let e = anyhow::Error::from(PotError::IncompleteSystemConf);
+        assert_eq!(error_code(&e), "incomplete-system-config");
+        assert_eq!(error_code(&anyhow::anyhow!("oops")), "internal-error");
     }
 
     #[test]
-    fn get_prefix_length_000() {
-        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
-        let uut = get_prefix_length(2, &ip_addr);
-        assert_eq!(uut, Some(30));
-    }
-    #[test]
-    fn get_prefix_length_001() {
-        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
-        let uut = get_prefix_length(5, &ip_addr);
-        assert_eq!(uut, Some(29));
+    fn bridge_sizes_fromstr_001() {
+        assert_eq!(
+            "web=30, db=6,".parse(),
+            Ok(BridgeSizes(vec![
+                ("web".to_string(), 30),
+                ("db".to_string(), 6)
+            ]))
+        );
+        assert!("web".parse::<BridgeSizes>().is_err());
+        assert!("web=lots".parse::<BridgeSizes>().is_err());
+        assert!("web=30,web=6".parse::<BridgeSizes>().is_err());
+        assert!("".parse::<BridgeSizes>().is_err());
     }
+
     #[test]
-    fn get_prefix_length_002() {
-        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
-        let uut = get_prefix_length(9, &ip_addr);
-        assert_eq!(uut, Some(28));
+    fn address_entries_001() {
+        let mut ip_db = IpDb::new();
+        ip_db.insert(
+            "10.192.0.3".parse().unwrap(),
+            Owner::Pot {
+                name: "web".to_string(),
+                network_type: NetType::PublicBridge,
+            },
+        );
+        ip_db.insert("10.192.0.1".parse().unwrap(), Owner::SystemGateway);
+        ip_db.insert(
+            "10.192.0.129".parse().unwrap(),
+            Owner::BridgeGateway {
+                bridge: "priv".to_string(),
+            },
+        );
+        let uut = serde_json::to_value(address_entries(&ip_db)).unwrap();
+        assert_eq!(
+            uut,
+            serde_json::json!([
+                {"ip": "10.192.0.1", "last": "10.192.0.1", "owner": {"kind": "system-gateway"}},
+                {"ip": "10.192.0.3", "last": "10.192.0.3",
+                 "owner": {"kind": "pot", "name": "web", "network_type": "public-bridge"}},
+                {"ip": "10.192.0.129", "last": "10.192.0.129",
+                 "owner": {"kind": "bridge-gateway", "name": "priv"}}
+            ])
+        );
     }
+
     #[test]
-    fn get_prefix_length_010() {
-        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
-        let uut = get_prefix_length(2, &ip_addr);
-        assert_eq!(uut, Some(126));
+    fn check_address_001() {
+        let network = "10.192.0.0/24".parse().unwrap();
+        assert_eq!(
+            check_address("gateway IP", "10.192.0.1".parse().unwrap(), &network),
+            None
+        );
+        assert_eq!(
+            check_address("gateway IP", "10.192.1.1".parse().unwrap(), &network),
+            Some("gateway IP (10.192.1.1) outside the network range (10.192.0.0/24)".to_string())
+        );
+        assert!(
+            check_address("gateway IP", "fd00::1".parse().unwrap(), &network)
+                .unwrap()
+                .contains("different families")
+        );
     }
+
     #[test]
-    fn get_prefix_length_011() {
-        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
-        let uut = get_prefix_length(5, &ip_addr);
-        assert_eq!(uut, Some(125));
+    fn parse_family_001() {
+        assert_eq!(parse_family("4"), Ok(IpStack::Ipv4));
+        assert_eq!(parse_family("both"), Ok(IpStack::Dual));
+        assert!(parse_family("ipv4").is_err());
     }
+
     #[test]
-    fn get_prefix_length_012() {
-        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
-        let uut = get_prefix_length(9, &ip_addr);
-        assert_eq!(uut, Some(124));
+    fn next_opt_001() {
+        let parse = |args: &[&str]| Opt::from_iter_safe(["potnet", "next"].iter().chain(args));
+        assert!(parse(&["-r", "-o", "web"]).is_ok());
+        assert!(parse(&["-o", "web"]).is_err());
+        assert!(parse(&["-r"]).is_err());
     }
 }